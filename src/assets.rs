@@ -0,0 +1,420 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use futures::{stream, StreamExt};
+use lazy_regex::regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::*;
+
+use crate::{site::normalize, utils::cpu_num};
+
+/// A local file referenced by a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// Source file of the first page referencing it.
+    pub page: String,
+}
+
+/// Outcome of [`Resources::copy`].
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    pub copied: usize,
+    /// Resources whose source does not exist.
+    pub missing: Vec<Resource>,
+    pub failed: Vec<(Resource, std::io::Error)>,
+}
+
+/// Local files referenced by the pages of the site, collected while rendering and copied to the
+/// output directory once every page has been written.
+#[derive(Debug)]
+pub struct Resources {
+    /// Resources are only copied inside of this directory.
+    output_dir: PathBuf,
+    items: Mutex<BTreeMap<PathBuf, Resource>>,
+}
+
+impl Resources {
+    pub fn new(output_dir: impl AsRef<Path>) -> Self {
+        Self {
+            output_dir: normalize(output_dir.as_ref()),
+            items: Default::default(),
+        }
+    }
+
+    /// Records `url`, found in the page built from `page`, relative to the source and output
+    /// directories of that page. Resources are keyed by destination, so a file referenced from
+    /// several pages is only copied once. URLs leading out of the output directory are ignored.
+    pub fn add(&self, page: &str, url: &str, source_dir: &Path, dest_dir: &Path) {
+        let source = normalize(&source_dir.join(url));
+        let dest = normalize(&dest_dir.join(url));
+        if !dest.starts_with(&self.output_dir) {
+            warn!(
+                "Ignore {url} referenced from {page}, it leads out of {}",
+                self.output_dir.display()
+            );
+            return;
+        }
+        self.items
+            .lock()
+            .unwrap()
+            .entry(dest.clone())
+            .or_insert_with(|| Resource {
+                source,
+                dest,
+                page: page.into(),
+            });
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies every resource to its destination. Files already written to the output directory,
+    /// such as diagrams, have no source and are left alone.
+    pub async fn copy(&self) -> CopyReport {
+        let items: Vec<Resource> = self.items.lock().unwrap().values().cloned().collect();
+        let results: Vec<_> = stream::iter(items.into_iter().map(|item| async move {
+            let result = Self::copy_one(&item).await;
+            (item, result)
+        }))
+        .buffer_unordered(cpu_num())
+        .collect()
+        .await;
+
+        let mut report = CopyReport::default();
+        for (item, result) in results {
+            match result {
+                Ok(true) => report.copied += 1,
+                Ok(false) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => report.missing.push(item),
+                Err(err) => report.failed.push((item, err)),
+            }
+        }
+        report.missing.sort_by(|a, b| a.source.cmp(&b.source));
+        report
+    }
+
+    /// Returns whether the file has been copied.
+    async fn copy_one(item: &Resource) -> std::io::Result<bool> {
+        if item.source == item.dest {
+            return Ok(false);
+        }
+        if !fs::try_exists(&item.source).await? {
+            if fs::try_exists(&item.dest).await? {
+                return Ok(false);
+            }
+            return Err(std::io::ErrorKind::NotFound.into());
+        }
+        if let Some(parent) = item.dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        debug!(
+            "Copy file: {} -> {}",
+            item.source.display(),
+            item.dest.display()
+        );
+        fs::copy(&item.source, &item.dest).await?;
+        Ok(true)
+    }
+}
+
+/// Number of hex digits of the content hash in fingerprinted file names.
+const HASH_LENGTH: usize = 8;
+
+/// Inserts the hash of `content` before the extension of `path`: `js/app.min.js` becomes
+/// `js/app.min.<hash>.js`.
+pub fn fingerprint_name(path: &str, content: &[u8]) -> String {
+    let hash = format!("{:x}", Sha256::digest(content));
+    let hash = &hash[..HASH_LENGTH];
+    let (dir, file) = match path.rsplit_once('/') {
+        Some((dir, file)) => (format!("{dir}/"), file),
+        None => (String::new(), path),
+    };
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{dir}{stem}.{hash}.{ext}"),
+        _ => format!("{dir}{file}.{hash}"),
+    }
+}
+
+/// Maps the path of every theme asset, relative to `assets/`, to the name it is published under.
+/// Written to `assets-manifest.json` at the root of the output directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AssetManifest {
+    entries: BTreeMap<String, String>,
+}
+
+impl AssetManifest {
+    /// Names the theme assets and rewrites the `url()`s of stylesheets to the new names. Returns
+    /// the manifest and the files to write under `assets/`. Stylesheets are hashed after their
+    /// references have been rewritten, so they change whenever a file they use does.
+    ///
+    /// Every asset is also written under its plain name, since scripts of the theme load their
+    /// chunks, and templates may link files, by the names they were built with.
+    pub fn build(
+        assets: BTreeMap<String, Vec<u8>>,
+        fingerprint: bool,
+    ) -> (Self, BTreeMap<String, Vec<u8>>) {
+        let mut manifest = Self::default();
+        let mut files = BTreeMap::new();
+        let (styles, others): (Vec<_>, Vec<_>) = assets
+            .into_iter()
+            .partition(|(path, _)| path.ends_with(".css"));
+        for (path, content) in others.into_iter().chain(styles) {
+            let content = match path.ends_with(".css") {
+                true => manifest
+                    .rewrite_css(&path, &String::from_utf8_lossy(&content))
+                    .into_bytes(),
+                false => content,
+            };
+            let name = match fingerprint {
+                true => fingerprint_name(&path, &content),
+                false => path.clone(),
+            };
+            if name != path {
+                files.insert(name.clone(), content.clone());
+            }
+            manifest.entries.insert(path.clone(), name);
+            files.insert(path, content);
+        }
+        (manifest, files)
+    }
+
+    /// Published name of the asset at `path`, relative to `assets/`.
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.entries.get(path).map(String::as_str)
+    }
+
+    /// Path of the asset relative to the output directory, e.g. `assets/style.<hash>.css`.
+    /// Unknown assets keep their name.
+    pub fn asset_path(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        format!("assets/{}", self.get(path).unwrap_or(path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Rewrites the `url()`s of the stylesheet at `path` that point to known assets.
+    pub fn rewrite_css(&self, path: &str, css: &str) -> String {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let re = regex!(r#"url\(\s*(['"]?)([^'")\s]+)(['"]?)\s*\)"#);
+        re.replace_all(css, |caps: &lazy_regex::Captures| {
+            let url = &caps[2];
+            if regex!(r#"^(\w[\w+.-]*:|//|#|/)"#).is_match(url) {
+                return caps[0].to_string();
+            }
+            let end = url.find(['?', '#']).unwrap_or(url.len());
+            let target = normalize(&dir.join(&url[..end]));
+            let Some(name) = self.get(&target.to_string_lossy()) else {
+                return caps[0].to_string();
+            };
+            let file = name.rsplit('/').next().unwrap_or(name);
+            let prefix = url[..end].rsplit_once('/').map(|(dir, _)| dir);
+            let url = match prefix {
+                Some(prefix) => format!("{prefix}/{file}{}", &url[end..]),
+                None => format!("{file}{}", &url[end..]),
+            };
+            format!("url({}{url}{})", &caps[1], &caps[3])
+        })
+        .into_owned()
+    }
+
+    /// Points the `href`, `src` and `url()` references of a rendered page at `<root>assets/<path>`
+    /// to the published names, `root` being the URL of the site root as seen from the page.
+    pub fn rewrite_html(&self, html: &str, root: &str) -> String {
+        let re = regex!(r#"((?:href|src)="|url\(['"]?)([^"'()]*?)assets/([^"'()?#]+)"#);
+        re.replace_all(html, |caps: &lazy_regex::Captures| {
+            let prefix = &caps[2];
+            // Other `assets/` directories belong to the content.
+            let in_theme = prefix == root || (root == "./" && prefix.is_empty());
+            match self.get(&caps[3]) {
+                Some(name) if in_theme => format!("{}{prefix}assets/{name}", &caps[1]),
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+    }
+
+    /// Writes the assets to `output_dir/assets` and the manifest to
+    /// `output_dir/assets-manifest.json`. The files listed by the manifest of a previous build
+    /// that are no longer published, such as assets hashed before they changed, are removed.
+    pub async fn write(
+        &self,
+        files: &BTreeMap<String, Vec<u8>>,
+        output_dir: &Path,
+    ) -> std::io::Result<()> {
+        let assets_dir = normalize(&output_dir.join("assets"));
+        let manifest_file = output_dir.join("assets-manifest.json");
+        let previous: Self = match fs::read(&manifest_file).await {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+            Err(_) => Self::default(),
+        };
+        for (path, content) in files {
+            let dest = assets_dir.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            debug!("Write asset: {}", dest.display());
+            fs::write(&dest, content).await?;
+        }
+        for (path, name) in &previous.entries {
+            for file in [path, name] {
+                let stale = normalize(&assets_dir.join(file));
+                if files.contains_key(file) || !stale.starts_with(&assets_dir) || !stale.exists() {
+                    continue;
+                }
+                debug!("Remove stale asset: {}", stale.display());
+                fs::remove_file(&stale).await?;
+            }
+        }
+        fs::write(manifest_file, serde_json::to_vec_pretty(self)?).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::Path};
+
+    use super::{fingerprint_name, AssetManifest, Resources};
+
+    #[tokio::test]
+    async fn test_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let content = root.join("content/a");
+        let public = root.join("public/a");
+        std::fs::create_dir_all(content.join("files")).unwrap();
+        std::fs::create_dir_all(&public).unwrap();
+        std::fs::write(content.join("files/spec.pdf"), "pdf").unwrap();
+        std::fs::write(root.join("content/logo.svg"), "svg").unwrap();
+        std::fs::write(public.join("diag-1.svg"), "generated").unwrap();
+
+        let resources = Resources::new(root.join("public"));
+        for url in [
+            "files/spec.pdf",
+            "./files/spec.pdf",
+            "../logo.svg",
+            "diag-1.svg",
+            "missing.mp4",
+            "../../secret.txt",
+            "/etc/passwd",
+        ] {
+            resources.add("content/a/index.adoc", url, &content, &public);
+        }
+        resources.add("content/a/other.adoc", "files/spec.pdf", &content, &public);
+        assert_eq!(resources.len(), 4);
+
+        let report = resources.copy().await;
+        assert_eq!(report.copied, 2);
+        assert!(report.failed.is_empty());
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].page, "content/a/index.adoc");
+        assert!(report.missing[0].source.ends_with("missing.mp4"));
+        assert_eq!(
+            std::fs::read_to_string(public.join("files/spec.pdf")).unwrap(),
+            "pdf"
+        );
+        assert!(Path::new(&root.join("public/logo.svg")).exists());
+    }
+
+    #[test]
+    fn test_fingerprint_name() {
+        let name = fingerprint_name("js/app.min.js", b"content");
+        assert!(name.starts_with("js/app.min."));
+        assert!(name.ends_with(".js"));
+        assert_eq!(name.len(), "js/app.min..js".len() + 8);
+        assert_eq!(name, fingerprint_name("js/app.min.js", b"content"));
+        assert_ne!(name, fingerprint_name("js/app.min.js", b"changed"));
+        assert!(fingerprint_name("LICENSE", b"").starts_with("LICENSE."));
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let mut assets = BTreeMap::new();
+        assets.insert("fonts/a.woff2".to_string(), b"font".to_vec());
+        assets.insert(
+            "css/style.css".to_string(),
+            br#"@font-face { src: url("../fonts/a.woff2?v=1") } a { background: url(data:x) }"#
+                .to_vec(),
+        );
+        let (manifest, files) = AssetManifest::build(assets, true);
+        let font = manifest.get("fonts/a.woff2").unwrap().to_string();
+        let style = manifest.get("css/style.css").unwrap().to_string();
+        assert_ne!(font, "fonts/a.woff2");
+        assert_eq!(files.len(), 4);
+
+        let css = String::from_utf8(files[&style].clone()).unwrap();
+        let font_file = font.strip_prefix("fonts/").unwrap();
+        assert!(css.contains(&format!(r#"url("../fonts/{font_file}?v=1")"#)));
+        assert!(css.contains("url(data:x)"));
+        assert_eq!(files["css/style.css"], files[&style]);
+
+        let html = manifest.rewrite_html(
+            r#"<link href="../assets/css/style.css"><img src="../assets/fonts/a.woff2"><a href="../assets/none.css"><img src="myassets/css/style.css"><script src="guide/assets/css/style.css"></script><img src="assets/css/style.css">"#,
+            "../",
+        );
+        assert_eq!(
+            html,
+            format!(
+                r#"<link href="../assets/{style}"><img src="../assets/{font}"><a href="../assets/none.css"><img src="myassets/css/style.css"><script src="guide/assets/css/style.css"></script><img src="assets/css/style.css">"#
+            )
+        );
+        assert_eq!(
+            manifest.rewrite_html(
+                r#"<link href="/docs/assets/css/style.css"><img src="/docs/guide/assets/css/style.css">"#,
+                "/docs/",
+            ),
+            format!(
+                r#"<link href="/docs/assets/{style}"><img src="/docs/guide/assets/css/style.css">"#
+            )
+        );
+        assert_eq!(
+            manifest.rewrite_html(r#"<link href="assets/css/style.css">"#, "./"),
+            format!(r#"<link href="assets/{style}">"#)
+        );
+        assert_eq!(manifest.asset_path("none.js"), "assets/none.js");
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        manifest.write(&files, root).await.unwrap();
+        let written: AssetManifest =
+            serde_json::from_slice(&std::fs::read(root.join("assets-manifest.json")).unwrap())
+                .unwrap();
+        assert_eq!(written, manifest);
+        assert!(root.join("assets").join(&style).exists());
+
+        // A changed stylesheet replaces the one hashed by the previous build.
+        let mut assets = BTreeMap::new();
+        assets.insert("css/style.css".to_string(), b"a { color: red }".to_vec());
+        let (changed, changed_files) = AssetManifest::build(assets, true);
+        changed.write(&changed_files, root).await.unwrap();
+        let new_style = changed.get("css/style.css").unwrap();
+        assert_ne!(new_style, style);
+        assert!(root.join("assets").join(new_style).exists());
+        assert!(root.join("assets/css/style.css").exists());
+        assert!(!root.join("assets").join(&style).exists());
+        assert!(!root.join("assets").join(&font).exists());
+        assert!(!root.join("assets/fonts/a.woff2").exists());
+
+        let (manifest, files) = AssetManifest::build(files, false);
+        assert_eq!(manifest.get("css/style.css"), Some("css/style.css"));
+        assert_eq!(files.len(), 4);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use futures::{stream, StreamExt};
+use lazy_regex::regex;
+use serde::Serialize;
+use tokio::fs;
+use tracing::*;
+
+use crate::{
+    generator::AdocGenerator,
+    markdown,
+    site::{normalize, IndexNode, Link},
+    source::SourceFormat,
+    utils::{cpu_num, HtmlParser, TextFilter},
+};
+
+/// A page of a book, with its ids prefixed by its anchor so that they stay unique.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Chapter {
+    pub anchor: String,
+    pub title: String,
+    /// Top-level sections of the page, linking to their anchors in the book.
+    pub sections: Vec<Link>,
+    pub content: String,
+}
+
+/// The pages of a section in reading order, combined into a single document.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Book {
+    pub title: String,
+    pub chapters: Vec<Chapter>,
+}
+
+/// Anchor of the chapter written from `path`, e.g. `chapter-ch1-intro` for `ch1/intro.html`.
+fn chapter_anchor(path: &str) -> String {
+    let path = path.strip_suffix(".html").unwrap_or(path);
+    let slug = regex!(r"[^A-Za-z0-9_]+").replace_all(path, "-");
+    format!("chapter-{}", slug.trim_matches('-'))
+}
+
+impl Book {
+    /// Combines `pages`, given as their output path relative to the book and the HTML generated
+    /// for them. Links between pages of the book become links to anchors, and the URLs of other
+    /// files are made relative to the book.
+    pub fn new(pages: &[(String, String)]) -> Self {
+        let anchors: BTreeSet<&str> = pages.iter().map(|(path, _)| path.as_str()).collect();
+        let mut chapters = Vec::with_capacity(pages.len());
+        for (path, html) in pages {
+            let parser = HtmlParser::new(html);
+            let anchor = chapter_anchor(path);
+            let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+            let mut content = parser.get_content().unwrap_or_default();
+            if let Some(footnotes) = parser.get_footnotes() {
+                content.push_str(&format!(r#"<div class="footnotes">{footnotes}</div>"#));
+            }
+            let content = regex!(r#"\sid="([^"]*)""#)
+                .replace_all(&content, |caps: &lazy_regex::Captures| {
+                    format!(r#" id="{anchor}--{}""#, &caps[1])
+                });
+            // Markers and gradients of inlined SVGs refer to their ids with `url(#…)`.
+            let content = regex!(r#"url\((['"]?)#([^)'"]+)(['"]?)\)"#).replace_all(
+                &content,
+                |caps: &lazy_regex::Captures| {
+                    format!("url({}#{anchor}--{}{})", &caps[1], &caps[2], &caps[3])
+                },
+            );
+            let content = regex!(r#"\s((?:xlink:)?href|src|data)="([^"]*)""#).replace_all(
+                &content,
+                |caps: &lazy_regex::Captures| {
+                    let url = &caps[2];
+                    let url = if let Some(fragment) = url.strip_prefix('#') {
+                        format!("#{anchor}--{fragment}")
+                    } else if url.is_empty() || regex!(r"^(\w[\w+.-]*:|//|/)").is_match(url) {
+                        url.to_string()
+                    } else {
+                        let (file, fragment) = url.split_once('#').unwrap_or((url, ""));
+                        let target = normalize(&dir.join(file));
+                        let target = target.to_string_lossy();
+                        match (anchors.contains(target.as_ref()), fragment) {
+                            (true, "") => format!("#{}", chapter_anchor(&target)),
+                            (true, _) => format!("#{}--{fragment}", chapter_anchor(&target)),
+                            (false, "") => target.into_owned(),
+                            (false, _) => format!("{target}#{fragment}"),
+                        }
+                    };
+                    format!(r#" {}="{url}""#, &caps[1])
+                },
+            );
+
+            let sections = parser
+                .sections(&TextFilter::default())
+                .into_iter()
+                .filter(|item| item.breadcrumb.is_empty())
+                .filter_map(|item| {
+                    Some(Link {
+                        url: format!("#{anchor}--{}", item.id?),
+                        title: item.heading,
+                    })
+                })
+                .collect();
+            chapters.push(Chapter {
+                title: parser.get_title(),
+                anchor,
+                sections,
+                content: content.into_owned(),
+            });
+        }
+
+        Self {
+            title: chapters
+                .first()
+                .map(|item| item.title.clone())
+                .unwrap_or_default(),
+            chapters,
+        }
+    }
+}
+
+/// A book document including `files`, given relative to it, one level below the book title.
+pub fn book_adoc(title: &str, files: &[String]) -> String {
+    let mut res = format!("= {title}\n:doctype: book\n:toc:\n");
+    for file in files {
+        res.push_str(&format!("\ninclude::{file}[leveloffset=+1]\n"));
+    }
+    res
+}
+
+/// The pages of a section generated for an export, with their resources published.
+#[derive(Debug)]
+pub struct SectionPages {
+    /// Source directory of the section, e.g. `content/book1`.
+    pub source_dir: PathBuf,
+    /// Output directory of the section, which books are written to.
+    pub dest_dir: PathBuf,
+    /// Source files in reading order.
+    pub files: Vec<String>,
+    /// Output path relative to `dest_dir` and HTML of every page, in reading order.
+    pub pages: Vec<(String, String)>,
+}
+
+/// Generates the pages of `section`, a directory of `content/` with an `index.adoc`, and copies
+/// the resources they reference.
+pub async fn load_section(
+    generator: &AdocGenerator,
+    section: &str,
+) -> anyhow::Result<SectionPages> {
+    let section = normalize(Path::new(section));
+    let section = match section.starts_with("content") {
+        true => section,
+        false => Path::new("content").join(section),
+    };
+    let index_file = section.join("index.adoc");
+    let index = IndexNode::parse(&index_file.to_string_lossy())
+        .with_context(|| format!("{} not found", index_file.display()))?;
+    let files = index.files();
+    let is_page = |file: &str| files.iter().any(|item| item == file);
+
+    let pages: Vec<_> = stream::iter(files.iter().map(|file| async move {
+        let (ctx, html) = generator.generate_html(file.into()).await?;
+        let html = markdown::rewrite_links(&html, Path::new(&ctx.source_dir), is_page);
+        let html = generator
+            .diagrams()
+            .process_page(&html, &ctx.source_dir, &ctx.dest_dir)
+            .await;
+        for url in HtmlParser::new(&html).resource_urls() {
+            generator.resources().add(
+                &ctx.source_file,
+                &url,
+                Path::new(&ctx.source_dir),
+                Path::new(&ctx.dest_dir),
+            );
+        }
+        Some((ctx, html))
+    }))
+    .buffered(cpu_num())
+    .filter_map(|item| async { item })
+    .collect()
+    .await;
+    let Some((index_ctx, _)) = pages.first() else {
+        anyhow::bail!("no page in {}", section.display());
+    };
+    let book_dir = PathBuf::from(&index_ctx.dest_dir);
+
+    let pages: Vec<_> = pages
+        .iter()
+        .map(|(ctx, html)| {
+            let path = Path::new(&ctx.dest_file)
+                .strip_prefix(&book_dir)
+                .unwrap_or(Path::new(&ctx.dest_file));
+            (path.to_string_lossy().into_owned(), html.clone())
+        })
+        .collect();
+    let report = generator.resources().copy().await;
+    for item in &report.missing {
+        warn!(
+            "Missing resource {} referenced from {}",
+            item.source.display(),
+            item.page
+        );
+    }
+
+    Ok(SectionPages {
+        source_dir: section,
+        dest_dir: book_dir,
+        files,
+        pages,
+    })
+}
+
+/// Exports the pages of `section` as `book.html` in its output directory, and also as
+/// `book.pdf` when `pdf` is set. Returns the files written.
+pub async fn export(
+    generator: &AdocGenerator,
+    section: &str,
+    pdf: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let SectionPages {
+        source_dir: section,
+        dest_dir: book_dir,
+        files,
+        pages,
+    } = load_section(generator, section).await?;
+    let book = Book::new(&pages);
+
+    let mut written = Vec::new();
+    let html_file = book_dir.join("book.html");
+    let document = generator.render_book(&book)?;
+    fs::write(&html_file, document).await?;
+    written.push(html_file);
+
+    if pdf {
+        let files: Vec<String> = files
+            .iter()
+            .filter_map(|file| {
+                if SourceFormat::from_path(file) != Some(SourceFormat::Asciidoc) {
+                    warn!("Leave {file} out of the PDF, asciidoctor only includes AsciiDoc");
+                    return None;
+                }
+                let path = Path::new(file).strip_prefix(&section).ok()?;
+                Some(path.to_string_lossy().into_owned())
+            })
+            .collect();
+        // Written next to the pages so that their includes and images resolve.
+        let adoc_file = section.join(".book.adoc");
+        let pdf_file = book_dir.join("book.pdf");
+        // A PDF left by an earlier run would hide a failed conversion.
+        match fs::remove_file(&pdf_file).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        fs::write(&adoc_file, book_adoc(&book.title, &files)).await?;
+        let mut builder = generator.asciidoctor(
+            adoc_file.to_string_lossy().into(),
+            book_dir.to_string_lossy().into(),
+        );
+        builder
+            .plugin("asciidoctor-pdf".into())
+            .backend("pdf".into())
+            .output(pdf_file.to_string_lossy().into());
+        builder.build().await;
+        let _ = fs::remove_file(&adoc_file).await;
+        if !pdf_file.exists() {
+            anyhow::bail!("asciidoctor-pdf did not write {}", pdf_file.display());
+        }
+        written.push(pdf_file);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{book_adoc, chapter_anchor, Book};
+    use crate::{config, generator::AdocGenerator, theme::Theme};
+
+    fn page(title: &str, content: &str) -> String {
+        format!(
+            r#"<html><head><title>{title}</title></head><body><div id="content">{content}</div></body></html>"#
+        )
+    }
+
+    #[test]
+    fn test_book() {
+        let pages = vec![
+            (
+                "index.html".to_string(),
+                page(
+                    "Book",
+                    r#"<p><a href="ch1/intro.html">Intro</a> <a href="ch1/intro.html#_setup">Setup</a></p>"#,
+                ),
+            ),
+            (
+                "ch1/intro.html".to_string(),
+                page(
+                    "Intro",
+                    r##"<div class="sect1"><h2 id="_setup">Setup</h2><div class="sectionbody"><p><a href="#_setup">here</a> <a href="../index.html">up</a> <img src="a.png"> <a href="https://example.com">x</a></p></div></div>"##,
+                ),
+            ),
+        ];
+        let book = Book::new(&pages);
+        assert_eq!(book.title, "Book");
+        assert_eq!(book.chapters.len(), 2);
+        assert_eq!(book.chapters[1].anchor, "chapter-ch1-intro");
+        assert_eq!(
+            book.chapters[0].content,
+            r##"<p><a href="#chapter-ch1-intro">Intro</a> <a href="#chapter-ch1-intro--_setup">Setup</a></p>"##
+        );
+        assert_eq!(
+            book.chapters[1].content,
+            r##"<div class="sect1"><h2 id="chapter-ch1-intro--_setup">Setup</h2><div class="sectionbody"><p><a href="#chapter-ch1-intro--_setup">here</a> <a href="#chapter-index">up</a> <img src="ch1/a.png"> <a href="https://example.com">x</a></p></div></div>"##
+        );
+        assert_eq!(book.chapters[1].sections.len(), 1);
+        assert_eq!(
+            book.chapters[1].sections[0].url,
+            "#chapter-ch1-intro--_setup"
+        );
+
+        let svg = r##"<svg><defs><marker id="m"></marker><linearGradient id="g"></linearGradient></defs><path marker-end="url(#m)" style="fill:url('#g')"></path><use xlink:href="#g"></use></svg>"##;
+        let book = Book::new(&[("a.html".into(), page("A", svg))]);
+        assert_eq!(
+            book.chapters[0].content,
+            r##"<svg><defs><marker id="chapter-a--m"></marker><linearGradient id="chapter-a--g"></linearGradient></defs><path marker-end="url(#chapter-a--m)" style="fill:url('#chapter-a--g')"></path><use xlink:href="#chapter-a--g"></use></svg>"##
+        );
+    }
+
+    #[test]
+    fn test_render_book() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let generator = AdocGenerator::new(
+            &theme,
+            config::Config::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let book = Book::new(&[("index.html".into(), page("Book", "<p>Hello</p>"))]);
+        let html = generator.render_book(&book).unwrap();
+        assert!(html.contains("<title>Book</title>"), "{html}");
+        assert!(
+            html.contains(r##"<a href="#chapter-index">Book</a>"##),
+            "{html}"
+        );
+        assert!(html.contains("<p>Hello</p>"), "{html}");
+        assert!(html.contains(".hl-comment"), "{html}");
+    }
+
+    #[test]
+    fn test_book_adoc() {
+        assert_eq!(chapter_anchor("a b/c.html"), "chapter-a-b-c");
+        assert_eq!(
+            book_adoc("Book", &["index.adoc".into(), "ch1/intro.adoc".into()]),
+            "= Book\n:doctype: book\n:toc:\n\ninclude::index.adoc[leveloffset=+1]\n\ninclude::ch1/intro.adoc[leveloffset=+1]\n"
+        );
+    }
+}
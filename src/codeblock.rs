@@ -0,0 +1,200 @@
+use std::collections::BTreeSet;
+
+use lazy_regex::regex;
+
+use crate::utils::{escape, unescape};
+
+/// Prefix of the role `src/utils/asphinx.rb` turns the `highlight` attribute into.
+const HIGHLIGHT_ROLE: &str = "asphinx-highlight-";
+
+fn escape_attr(text: &str) -> String {
+    escape(text).replace('\n', "&#10;")
+}
+
+/// Line numbers of a `highlight` attribute such as `1,3..5` or `2-4;7`, starting at 1.
+pub fn parse_lines(spec: &str) -> BTreeSet<usize> {
+    let mut res = BTreeSet::new();
+    for part in spec.split([',', ';']) {
+        let range = part.split_once("..").or_else(|| part.split_once('-'));
+        match range {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse()) {
+                    res.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(line) = part.trim().parse() {
+                    res.insert(line);
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Text of a line of code as it is copied: markup and callouts removed.
+fn plain_text(line: &str) -> String {
+    let line = regex!(r#"<i class="conum"[^>]*></i>|<b(?: class="conum")?>\(\d+\)</b>"#)
+        .replace_all(line, "");
+    let line = regex!(r"<[^>]*>").replace_all(&line, "");
+    unescape(line.trim_end())
+}
+
+/// Whether every line of `code` closes the elements it opens, which is how highlighted code is
+/// emitted.
+fn is_balanced(code: &str) -> bool {
+    code.split('\n').all(|line| {
+        let opened = regex!(r"<[a-z][^>]*>").find_iter(line).count();
+        let closed = regex!(r"</[a-z][^>]*>").find_iter(line).count();
+        opened == closed
+    })
+}
+
+/// Enriches the listing blocks of a page:
+///
+/// - `<pre>` gets a `data-copy` attribute with the text to copy, for the copy button of themes.
+/// - Every line is wrapped in `<span class="line">`, with `highlighted` for the lines of the
+///   `highlight` attribute, and `diff-add` / `diff-remove` for lines starting with `+` / `-` in
+///   `diff` blocks or blocks with the `diff` role.
+/// - Titles looking like a file name get the `filename` class.
+///
+/// With the `diff` role, removed lines and the markers are left out of the copied text.
+pub fn enrich(html: &str) -> String {
+    let re = regex!(
+        r#"(?s)<div((?:\s+id="[^"]*")?)\s+class="listingblock([^"]*)">(\s*)(?:<div class="title">(.*?)</div>(\s*))?<div class="content">(\s*)<pre([^>]*)>(.*?)</pre>"#
+    );
+    re.replace_all(html, |caps: &lazy_regex::Captures| {
+        let mut highlighted = BTreeSet::new();
+        let mut classes = vec!["listingblock"];
+        for role in caps[2].split_whitespace() {
+            match role.strip_prefix(HIGHLIGHT_ROLE) {
+                Some(spec) => highlighted.extend(parse_lines(spec)),
+                None => classes.push(role),
+            }
+        }
+
+        let inner = &caps[8];
+        let (open, code, close) = match regex!(r"(?s)^(<code[^>]*>)(.*)(</code>)$").captures(inner)
+        {
+            Some(code) => (
+                code.get(1).unwrap().as_str(),
+                code.get(2).unwrap().as_str(),
+                code.get(3).unwrap().as_str(),
+            ),
+            None => ("", inner, ""),
+        };
+        let is_diff_lang = open.contains(r#"data-lang="diff""#);
+        let is_diff = is_diff_lang || classes.contains(&"diff");
+        let strip_markers = is_diff && !is_diff_lang;
+
+        let mut copy = Vec::new();
+        let mut lines = Vec::new();
+        let wrap = is_balanced(code);
+        for (idx, line) in code.split('\n').enumerate() {
+            let text = plain_text(line);
+            let mut line_classes = vec!["line"];
+            if highlighted.contains(&(idx + 1)) {
+                line_classes.push("highlighted");
+            }
+            if is_diff && text.starts_with('+') {
+                line_classes.push("diff-add");
+            } else if is_diff && text.starts_with('-') {
+                line_classes.push("diff-remove");
+            }
+
+            match (strip_markers, text.chars().next()) {
+                (true, Some('-')) => {}
+                (true, Some('+')) => copy.push(text[1..].to_string()),
+                _ => copy.push(text),
+            }
+            lines.push(match wrap {
+                true => format!(r#"<span class="{}">{line}</span>"#, line_classes.join(" ")),
+                false => line.to_string(),
+            });
+        }
+
+        let title = caps.get(4).map(|title| {
+            let is_file = regex!(r"^[\w./-]+\.\w+$").is_match(title.as_str());
+            let class = if is_file { "title filename" } else { "title" };
+            format!(
+                r#"<div class="{class}">{}</div>{}"#,
+                title.as_str(),
+                &caps[5]
+            )
+        });
+        format!(
+            r#"<div{} class="{}">{}{}<div class="content">{}<pre{} data-copy="{}">{open}{}{close}</pre>"#,
+            &caps[1],
+            classes.join(" "),
+            &caps[3],
+            title.unwrap_or_default(),
+            &caps[6],
+            &caps[7],
+            escape_attr(&copy.join("\n")),
+            lines.join("\n"),
+        )
+    })
+    .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{enrich, parse_lines};
+
+    #[test]
+    fn test_parse_lines() {
+        assert_eq!(
+            parse_lines("1, 3..5;7-8,x"),
+            BTreeSet::from([1, 3, 4, 5, 7, 8])
+        );
+    }
+
+    #[test]
+    fn test_enrich() {
+        let html = r#"<div class="listingblock asphinx-highlight-2..3">
+<div class="title">src/main.rs</div>
+<div class="content">
+<pre class="highlight"><code data-lang="rust"><span class="hl-a">fn</span> main() {
+    <span class="hl-b">let</span> a = &quot;&lt;&quot;; <b class="conum">(1)</b>
+}</code></pre>
+</div>
+</div>"#;
+        assert_eq!(
+            enrich(html),
+            r#"<div class="listingblock">
+<div class="title filename">src/main.rs</div>
+<div class="content">
+<pre class="highlight" data-copy="fn main() {&#10;    let a = &quot;&lt;&quot;;&#10;}"><code data-lang="rust"><span class="line"><span class="hl-a">fn</span> main() {</span>
+<span class="line highlighted">    <span class="hl-b">let</span> a = &quot;&lt;&quot;; <b class="conum">(1)</b></span>
+<span class="line highlighted">}</span></code></pre>
+</div>
+</div>"#
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let html = r#"<div id="fix" class="listingblock diff"><div class="title">Fix the loop</div><div class="content"><pre>for x in a {
+-    f(x);
++    g(x);
+}</pre></div></div>"#;
+        assert_eq!(
+            enrich(html),
+            r#"<div id="fix" class="listingblock diff"><div class="title">Fix the loop</div><div class="content"><pre data-copy="for x in a {&#10;    g(x);&#10;}"><span class="line">for x in a {</span>
+<span class="line diff-remove">-    f(x);</span>
+<span class="line diff-add">+    g(x);</span>
+<span class="line">}</span></pre></div></div>"#
+        );
+
+        let patch = r#"<div class="listingblock"><div class="content"><pre class="highlight"><code data-lang="diff">-a
++b</code></pre></div></div>"#;
+        let res = enrich(patch);
+        assert!(res.contains(r#"data-copy="-a&#10;+b""#), "{res}");
+        assert!(
+            res.contains(r#"<span class="line diff-add">+b</span>"#),
+            "{res}"
+        );
+    }
+}
@@ -1,9 +1,28 @@
 mod asciidoc;
+mod assets;
+mod diagrams;
+mod filetree;
+mod highlight;
+mod images;
+mod math;
+mod plugins;
+mod search;
+mod summary;
+mod theme;
 
 use std::{path::Path, str::FromStr};
 
 pub use asciidoc::Asciidoc;
+pub use diagrams::Diagrams;
+pub use filetree::{Compression, FileTree, FileTreeFormat};
+pub use highlight::Highlight;
+pub use images::{ImageFormat, Images};
+pub use math::MathEngine;
+pub use plugins::{Hook, Plugin};
+pub use search::Search;
 use serde::{Deserialize, Serialize};
+pub use summary::Summary;
+pub use theme::Theme;
 use tokio::fs;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,8 +31,32 @@ pub struct Config {
     pub no_default: bool,
     #[serde(default)]
     pub asciidoc: asciidoc::Asciidoc,
+    /// Base URL or path the site is deployed under, e.g. `https://corp.example/docs/`.
     #[serde(default)]
     pub site: String,
+    /// Emit links relative to the current page so the site also works from `file://`.
+    #[serde(default)]
+    pub relative_urls: bool,
+    #[serde(default)]
+    pub theme: theme::Theme,
+    #[serde(default)]
+    pub filetree: filetree::FileTree,
+    #[serde(default)]
+    pub search: search::Search,
+    #[serde(default)]
+    pub summary: summary::Summary,
+    #[serde(default)]
+    pub assets: assets::Assets,
+    #[serde(default)]
+    pub images: images::Images,
+    #[serde(default)]
+    pub diagrams: diagrams::Diagrams,
+    #[serde(default)]
+    pub math: math::Math,
+    #[serde(default)]
+    pub highlight: highlight::Highlight,
+    #[serde(default)]
+    pub plugins: Vec<plugins::Plugin>,
 }
 
 impl Default for Config {
@@ -27,9 +70,28 @@ impl Config {
         let default = Self::default();
 
         self.asciidoc.merge(patch.asciidoc);
+        self.theme.merge(patch.theme);
+        self.filetree.merge(patch.filetree);
+        self.search.merge(patch.search);
+        self.summary.merge(patch.summary);
+        self.assets.merge(patch.assets);
+        self.images.merge(patch.images);
+        self.diagrams.merge(patch.diagrams);
+        self.math.merge(patch.math);
+        self.highlight.merge(patch.highlight);
+        self.plugins.extend(patch.plugins);
         if patch.site != default.site {
             self.site = patch.site;
         }
+        if patch.relative_urls != default.relative_urls {
+            self.relative_urls = patch.relative_urls;
+        }
+    }
+
+    /// Whether asphinx highlights source blocks, which it leaves to asciidoctor when a
+    /// `source-highlighter` is set.
+    pub fn build_highlighting(&self) -> bool {
+        self.highlight.enabled && !self.asciidoc.attributes.contains_key("source-highlighter")
     }
 
     pub async fn from_path(path: impl AsRef<Path>) -> Self {
@@ -121,4 +183,39 @@ plantuml-format = "svg"
 
         assert!(config.asciidoc.attributes.contains_key("plantuml-format"));
     }
+
+    #[test]
+    fn test_build_highlighting() {
+        let config = Config::default();
+        assert!(config.build_highlighting());
+        let config = Config::from_str(
+            r#"
+[asciidoc.attributes]
+source-highlighter = "rouge"
+        "#,
+        )
+        .unwrap();
+        assert!(!config.build_highlighting());
+    }
+
+    #[test]
+    fn test_no_default() {
+        // Sections left out still get usable values without the defaults merged in.
+        let config: Config = toml::from_str("no_default = true").unwrap();
+        assert_eq!(config.summary.length, 200);
+        assert_eq!(config.summary.words_per_minute, 200);
+        assert_eq!(config.summary.cjk_per_minute, 400);
+        assert_eq!(config.search, Config::default().search);
+    }
+
+    #[test]
+    fn test_merge_search() {
+        let mut config = Config::default();
+        config.merge(toml::from_str("[search]\nexclude_code = true").unwrap());
+        assert_eq!(config.search.exclude_selectors.len(), 4);
+        assert!(config.search.exclude_code);
+
+        config.merge(toml::from_str("[search]\nexclude_selectors = []").unwrap());
+        assert!(config.search.exclude_selectors.is_empty());
+    }
 }
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Assets {
+    /// Publish theme assets under names containing a hash of their content.
+    pub fingerprint: bool,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self { fingerprint: true }
+    }
+}
+
+impl Assets {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.fingerprint != default.fingerprint {
+            self.fingerprint = patch.fingerprint;
+        }
+    }
+}
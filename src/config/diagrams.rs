@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Diagrams {
+    /// Strip comments and whitespace from SVG diagrams.
+    pub minify: bool,
+    /// Embed SVG diagrams in the page instead of linking them.
+    pub inline: bool,
+}
+
+impl Diagrams {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.minify != default.minify {
+            self.minify = patch.minify;
+        }
+        if patch.inline != default.inline {
+            self.inline = patch.inline;
+        }
+    }
+}
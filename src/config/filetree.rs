@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileTreeFormat {
+    /// `root` with nested children.
+    #[default]
+    Nested,
+    /// A flat array of nodes referring to their parent by index.
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    #[serde(alias = "brotli")]
+    Br,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileTree {
+    pub format: FileTreeFormat,
+    /// Precompressed variants written next to every file tree file.
+    pub compress: Vec<Compression>,
+    /// Write the subtree of every top-level section to its own file.
+    pub shard: bool,
+}
+
+impl FileTree {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.format != default.format {
+            self.format = patch.format;
+        }
+        if patch.compress != default.compress {
+            self.compress = patch.compress;
+        }
+        if patch.shard != default.shard {
+            self.shard = patch.shard;
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Highlight {
+    /// Highlight source blocks while building, which needs `source-highlighter` to be unset.
+    pub enabled: bool,
+    pub light_theme: String,
+    /// Used when the reader prefers a dark color scheme.
+    pub dark_theme: String,
+}
+
+impl Default for Highlight {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            light_theme: "InspiredGitHub".into(),
+            dark_theme: "base16-ocean.dark".into(),
+        }
+    }
+}
+
+impl Highlight {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.enabled != default.enabled {
+            self.enabled = patch.enabled;
+        }
+        if patch.light_theme != default.light_theme {
+            self.light_theme = patch.light_theme;
+        }
+        if patch.dark_theme != default.dark_theme {
+            self.dark_theme = patch.dark_theme;
+        }
+    }
+}
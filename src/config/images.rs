@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Webp,
+    /// Only available when asphinx is built with the `avif` feature.
+    Avif,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Images {
+    /// Generate resized variants of content images and link them with `srcset`.
+    pub enabled: bool,
+    /// Widths of the resized variants, in pixels. Images are never enlarged.
+    pub widths: Vec<u32>,
+    /// Formats written in addition to the format of the original image.
+    pub formats: Vec<ImageFormat>,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            widths: vec![480, 960, 1600],
+            formats: vec![ImageFormat::Webp],
+        }
+    }
+}
+
+impl Images {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.enabled != default.enabled {
+            self.enabled = patch.enabled;
+        }
+        if patch.widths != default.widths {
+            self.widths = patch.widths;
+        }
+        if patch.formats != default.formats {
+            self.formats = patch.formats;
+        }
+    }
+}
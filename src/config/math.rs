@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MathEngine {
+    /// Convert LaTeX math to MathML while building.
+    Mathml,
+    /// Let the asciidoctor-mathematical extension render formulas to images.
+    #[default]
+    AsciidoctorMathematical,
+    /// Leave the `\(...\)` and `\[...\]` delimiters for a script of the theme.
+    Client,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Math {
+    pub engine: MathEngine,
+}
+
+impl Math {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.engine != default.engine {
+            self.engine = patch.engine;
+        }
+    }
+}
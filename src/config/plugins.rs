@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// Stages of the build plugins can hook into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Hook {
+    /// The source of a page has been read, before it is converted.
+    SourceLoaded,
+    /// A page has been converted to HTML, before asphinx processes it.
+    RawHtml,
+    /// The template context of a page has been built.
+    Document,
+    /// A page has been rendered with its template, before it is written.
+    PageRendered,
+    /// Every file of the site has been written.
+    SiteFinished,
+}
+
+/// An executable called for every hook it subscribes to, with a JSON request on its standard
+/// input and a JSON response on its standard output.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Plugin {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    pub hooks: Vec<Hook>,
+    /// Seconds a call may take before the command is killed and the call counted as failed.
+    pub timeout: u64,
+}
+
+impl Default for Plugin {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            command: String::new(),
+            args: Vec::new(),
+            hooks: Vec::new(),
+            timeout: 30,
+        }
+    }
+}
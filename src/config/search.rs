@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// Options of the pagefind index, unset values keep pagefind's defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Search {
+    /// Element pagefind treats as the root of every page.
+    pub root_selector: Option<String>,
+    /// Elements left out of the indexes and of the text extracted from pages.
+    pub exclude_selectors: Vec<String>,
+    /// Index the whole site as a single language, as an ISO 639-1 code.
+    pub language: Option<String>,
+    /// Files of the output directory to index.
+    pub glob: Option<String>,
+    /// Leave code blocks out of the indexes.
+    pub exclude_code: bool,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            root_selector: None,
+            exclude_selectors: [".toc", ".conum", ".linenos", "sup.footnote"]
+                .map(String::from)
+                .to_vec(),
+            language: None,
+            glob: None,
+            exclude_code: false,
+        }
+    }
+}
+
+impl Search {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.root_selector.is_some() {
+            self.root_selector = patch.root_selector;
+        }
+        if patch.exclude_selectors != default.exclude_selectors {
+            self.exclude_selectors = patch.exclude_selectors;
+        }
+        if patch.language.is_some() {
+            self.language = patch.language;
+        }
+        if patch.glob.is_some() {
+            self.glob = patch.glob;
+        }
+        if patch.exclude_code != default.exclude_code {
+            self.exclude_code = patch.exclude_code;
+        }
+    }
+}
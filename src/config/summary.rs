@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Summary {
+    /// Maximum length of a summary, in characters.
+    pub length: usize,
+    /// Reading speed for words written with spaces between them.
+    pub words_per_minute: usize,
+    /// Reading speed for Chinese, Japanese and Korean text, in characters.
+    pub cjk_per_minute: usize,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            length: 200,
+            words_per_minute: 200,
+            cjk_per_minute: 400,
+        }
+    }
+}
+
+impl Summary {
+    pub fn merge(&mut self, patch: Self) {
+        let default = Self::default();
+
+        if patch.length != default.length {
+            self.length = patch.length;
+        }
+        if patch.words_per_minute != default.words_per_minute {
+            self.words_per_minute = patch.words_per_minute;
+        }
+        if patch.cjk_per_minute != default.cjk_per_minute {
+            self.cjk_per_minute = patch.cjk_per_minute;
+        }
+    }
+}
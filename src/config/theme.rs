@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Overrides for the params declared in the theme's `theme.toml`.
+    pub params: Table,
+}
+
+impl Theme {
+    pub fn merge(&mut self, patch: Self) {
+        self.params.extend(patch.params);
+    }
+}
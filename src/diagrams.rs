@@ -0,0 +1,360 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lazy_regex::{regex, regex::NoExpand};
+use tokio::fs;
+use tracing::*;
+
+use crate::{config, site::normalize};
+
+/// Shrinks an SVG by dropping comments and the whitespace between tags on separate lines.
+pub fn minify_svg(svg: &str) -> String {
+    let svg = regex!(r"(?s)<!--.*?-->").replace_all(svg, "");
+    regex!(r">\s*\n\s*<")
+        .replace_all(&svg, "><")
+        .trim()
+        .to_string()
+}
+
+/// Prepends `prefix` to the ids defined in an SVG and to the references to them, so that several
+/// SVGs can share a page.
+pub fn prefix_ids(svg: &str, prefix: &str) -> String {
+    let ids: HashSet<&str> = regex!(r#"\sid="([^"]+)""#)
+        .captures_iter(svg)
+        .map(|caps| caps.get(1).unwrap().as_str())
+        .collect();
+    if ids.is_empty() {
+        return svg.into();
+    }
+    regex!(r##"(\sid="|url\(#|href="#)([^")]+)"##)
+        .replace_all(svg, |caps: &lazy_regex::Captures| {
+            match ids.contains(&caps[2]) {
+                true => format!("{}{prefix}{}", &caps[1], &caps[2]),
+                false => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Turns an SVG file into an element embeddable in a page: the XML declaration and doctype are
+/// removed, ids are prefixed with `id_prefix`, black strokes and fills follow the text color of
+/// the page, and the alternative text of the image becomes its label.
+pub fn inline_svg(svg: &str, alt: Option<&str>, id_prefix: &str) -> String {
+    let svg = regex!(r"(?s)<\?xml.*?\?>|<!DOCTYPE[^>]*>").replace_all(svg, "");
+    let svg = prefix_ids(&svg, id_prefix);
+    let svg = regex!(r#"\b(fill|stroke)="(#000000|#000|black|#181818)""#)
+        .replace_all(&svg, r#"$1="currentColor""#);
+    let svg = regex!(r#"(?i)\b(fill|stroke):\s*(#000000|#000|black|#181818)\b"#)
+        .replace_all(&svg, "$1:currentColor");
+    let label = alt
+        .map(|alt| format!(r#" role="img" aria-label="{alt}""#))
+        .unwrap_or_default();
+    regex!(r"<svg\b")
+        .replace(
+            svg.trim(),
+            NoExpand(&format!(r#"<svg class="diagram"{label}"#)),
+        )
+        .into_owned()
+}
+
+/// Diagrams rendered by asciidoctor-diagram. They are written to a cache directory mirroring
+/// the content directory, where asciidoctor-diagram skips the diagrams whose source did not change, and
+/// copied or inlined into the pages referring to them.
+#[derive(Debug)]
+pub struct Diagrams {
+    config: config::Diagrams,
+    /// Only diagrams referenced inside of this directory are published.
+    content_dir: PathBuf,
+    /// Diagrams are only written inside of this directory.
+    output_dir: PathBuf,
+    cache_dir: PathBuf,
+    /// Diagrams referenced by the pages built so far, as paths in the cache.
+    used: Mutex<BTreeSet<PathBuf>>,
+    /// Diagrams written to the output directory by this build.
+    published: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl Diagrams {
+    pub fn new(
+        config: &config::Diagrams,
+        content_dir: impl AsRef<Path>,
+        output_dir: impl AsRef<Path>,
+        cache_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            config: config.clone(),
+            content_dir: normalize(content_dir.as_ref()),
+            output_dir: normalize(output_dir.as_ref()),
+            cache_dir: cache_dir.into(),
+            used: Default::default(),
+            published: Default::default(),
+        }
+    }
+
+    /// File listing the diagrams written to the output directory, so that the next build only
+    /// removes files asphinx wrote.
+    fn published_list(&self) -> PathBuf {
+        self.cache_dir.join("published.json")
+    }
+
+    /// Directory asciidoctor-diagram writes the diagrams of the pages in `source_dir` to.
+    pub fn out_dir(&self, source_dir: &str) -> PathBuf {
+        let dir = normalize(Path::new(source_dir));
+        self.cache_dir
+            .join(dir.strip_prefix(&self.content_dir).unwrap_or(&dir))
+    }
+
+    /// Publishes the diagrams referenced by a page built from `source_dir` to `dest_dir`, and
+    /// inlines SVG diagrams when configured to. References leading out of the content or output
+    /// directory are left alone.
+    pub async fn process_page(&self, html: &str, source_dir: &str, dest_dir: &str) -> String {
+        let out_dir = self.out_dir(source_dir);
+        let mut res = String::with_capacity(html.len());
+        let mut last = 0;
+        let re = regex!(r#"<(img|object)\b[^>]*\s(?:src|data)="([^"]+)"[^>]*>"#);
+        let mut inlined = 0;
+        for caps in re.captures_iter(html) {
+            let url = &caps[2];
+            let Some(file_name) = url.rsplit('/').next().filter(|_| !url.contains("://")) else {
+                continue;
+            };
+            let cached = out_dir.join(file_name);
+            let Ok(content) = fs::read(&cached).await else {
+                continue;
+            };
+            let source = normalize(&Path::new(source_dir).join(url));
+            let dest = normalize(&Path::new(dest_dir).join(url));
+            if !source.starts_with(&self.content_dir) || !dest.starts_with(&self.output_dir) {
+                warn!(
+                    "Ignore diagram {url}, it leads out of {} or {}",
+                    self.content_dir.display(),
+                    self.output_dir.display()
+                );
+                continue;
+            }
+            self.used.lock().unwrap().insert(cached.clone());
+
+            let is_svg = file_name.ends_with(".svg");
+            let content = match is_svg && self.config.minify {
+                true => minify_svg(&String::from_utf8_lossy(&content)).into_bytes(),
+                false => content,
+            };
+            if is_svg && self.config.inline && &caps[1] == "img" {
+                let alt = regex!(r#"\salt="([^"]*)""#)
+                    .captures(&caps[0])
+                    .map(|caps| caps.get(1).unwrap().as_str());
+                let whole = caps.get(0).unwrap();
+                inlined += 1;
+                let stem = file_name.trim_end_matches(".svg");
+                res.push_str(&html[last..whole.start()]);
+                res.push_str(&inline_svg(
+                    &String::from_utf8_lossy(&content),
+                    alt,
+                    &format!("{stem}-{inlined}-"),
+                ));
+                last = whole.end();
+                continue;
+            }
+
+            self.published.lock().unwrap().insert(dest.clone());
+            if let Some(parent) = dest.parent() {
+                let _ = fs::create_dir_all(parent).await;
+            }
+            if let Err(err) = fs::write(&dest, content).await {
+                error!("Failed write diagram {}: {err}", dest.display());
+            }
+        }
+        res.push_str(&html[last..]);
+        res
+    }
+
+    /// Removes the diagrams no page referenced during this build: cached diagrams together with
+    /// the files asciidoctor-diagram keeps next to them, and the files of the output directory an
+    /// earlier build published. Returns the number of files removed.
+    pub fn clean(&self) -> usize {
+        let used = self.used.lock().unwrap();
+        let published = self.published.lock().unwrap();
+        // `diag-1.svg` is kept along with `diag-1.svg.cache` or `diag-1.cache`.
+        let stems: BTreeSet<PathBuf> = used
+            .iter()
+            .map(|path| match path.extension() {
+                Some(_) => path.with_extension(""),
+                None => path.clone(),
+            })
+            .collect();
+        let is_stale = |path: &Path| {
+            let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+                return false;
+            };
+            !name
+                .match_indices('.')
+                .any(|(idx, _)| stems.contains(&path.with_file_name(&name[..idx])))
+        };
+
+        let mut removed = 0;
+        let mut remove = |path: &Path| {
+            debug!("Remove stale diagram: {}", path.display());
+            match std::fs::remove_file(path) {
+                Ok(_) => removed += 1,
+                Err(err) => warn!("Remove {} failed: {err}", path.display()),
+            }
+        };
+        let list = self.published_list();
+        let previous: Vec<PathBuf> = std::fs::read(&list)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        for path in walk(&self.cache_dir) {
+            if path != list && is_stale(&path) {
+                remove(&path);
+            }
+        }
+        for path in previous {
+            if path.starts_with(&self.output_dir) && !published.contains(&path) && path.exists() {
+                remove(&path);
+            }
+        }
+        let res = std::fs::create_dir_all(&self.cache_dir)
+            .and_then(|_| std::fs::write(&list, serde_json::to_vec_pretty(&*published)?));
+        if let Err(err) = res {
+            warn!("Write {} failed: {err}", list.display());
+        }
+        removed
+    }
+}
+
+/// Files under `dir`, recursively.
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let mut res = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return res;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => res.extend(walk(&path)),
+            Ok(_) => res.push(path),
+            Err(_) => {}
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::{inline_svg, minify_svg, prefix_ids, Diagrams};
+    use crate::config;
+
+    const SVG: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated -->
+<svg xmlns="http://www.w3.org/2000/svg" width="10">
+  <rect fill="#FFFFFF" stroke="#181818"/>
+  <text style="fill:#000000;font-size:12px">a b</text>
+</svg>
+"##;
+
+    #[test]
+    fn test_svg() {
+        assert_eq!(
+            minify_svg(SVG),
+            r##"<?xml version="1.0" encoding="UTF-8"?><svg xmlns="http://www.w3.org/2000/svg" width="10"><rect fill="#FFFFFF" stroke="#181818"/><text style="fill:#000000;font-size:12px">a b</text></svg>"##
+        );
+        assert_eq!(
+            inline_svg(&minify_svg(SVG), Some("Flow"), "d-"),
+            r##"<svg class="diagram" role="img" aria-label="Flow" xmlns="http://www.w3.org/2000/svg" width="10"><rect fill="#FFFFFF" stroke="currentColor"/><text style="fill:currentColor;font-size:12px">a b</text></svg>"##
+        );
+    }
+
+    #[test]
+    fn test_prefix_ids() {
+        let svg = r##"<svg><defs><marker id="arrow"/><linearGradient id="g"/></defs><path marker-end="url(#arrow)" style="fill:url(#g)"/><use xlink:href="#g"/><a href="#top"/></svg>"##;
+        assert_eq!(
+            prefix_ids(svg, "d1-"),
+            r##"<svg><defs><marker id="d1-arrow"/><linearGradient id="d1-g"/></defs><path marker-end="url(#d1-arrow)" style="fill:url(#d1-g)"/><use xlink:href="#d1-g"/><a href="#top"/></svg>"##
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let cache = root.join("cache");
+        let public = root.join("public/a");
+        let content = root.join("content");
+        let source_dir = content.join("a");
+        let source_dir = source_dir.to_str().unwrap();
+        let config = config::Diagrams {
+            minify: true,
+            inline: false,
+        };
+        let diagrams = Diagrams::new(&config, &content, root.join("public"), &cache);
+        let out_dir = diagrams.out_dir(source_dir);
+        assert_eq!(out_dir, cache.join("a"));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::create_dir_all(&public).unwrap();
+        for name in [
+            "diag-1.svg",
+            "diag-1.svg.cache",
+            "diag-2.png",
+            "diag-2.cache",
+        ] {
+            std::fs::write(out_dir.join(name), SVG).unwrap();
+        }
+
+        // An earlier build published both diagrams.
+        let previous = Diagrams::new(&config, &content, root.join("public"), &cache);
+        previous
+            .process_page(
+                r#"<img src="diag-1.svg"><img src="diag-2.png">"#,
+                source_dir,
+                public.to_str().unwrap(),
+            )
+            .await;
+        assert_eq!(previous.clean(), 0);
+        assert!(public.join("diag-2.png").exists());
+        std::fs::write(public.join("diag-mine.png"), "content").unwrap();
+
+        let html = r#"<img src="diag-1.svg" alt="Flow"><img src="photo.png">"#;
+        let res = diagrams
+            .process_page(html, source_dir, public.to_str().unwrap())
+            .await;
+        assert_eq!(res, html);
+        assert_eq!(
+            std::fs::read_to_string(public.join("diag-1.svg")).unwrap(),
+            minify_svg(SVG)
+        );
+
+        let inline = Diagrams::new(
+            &config::Diagrams {
+                inline: true,
+                ..config
+            },
+            &content,
+            root.join("public"),
+            &cache,
+        );
+        let res = inline
+            .process_page(html, source_dir, public.to_str().unwrap())
+            .await;
+        assert!(res.starts_with(r#"<svg class="diagram" role="img" aria-label="Flow""#));
+        assert!(res.ends_with(r#"</svg><img src="photo.png">"#));
+
+        // References leading out of the output directory are not published.
+        let html = r#"<img src="../../diag-1.svg">"#;
+        let res = diagrams
+            .process_page(html, source_dir, public.to_str().unwrap())
+            .await;
+        assert_eq!(res, html);
+        assert!(!root.join("diag-1.svg").exists());
+
+        assert_eq!(diagrams.clean(), 3);
+        assert!(out_dir.join("diag-1.svg.cache").exists());
+        assert!(!out_dir.join("diag-2.cache").exists());
+        assert!(public.join("diag-1.svg").exists());
+        assert!(!public.join("diag-2.png").exists());
+        assert!(public.join("diag-mine.png").exists());
+    }
+}
@@ -0,0 +1,580 @@
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use lazy_regex::regex;
+use scraper::{ElementRef, Html, Node};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::*;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    book::{load_section, Book, SectionPages},
+    generator::AdocGenerator,
+    highlight,
+    site::normalize,
+    utils::{adoc, escape},
+};
+
+static VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+static STYLE: &str = r#"pre { white-space: pre-wrap; }
+img, svg { max-width: 100%; height: auto; }
+pre .line.highlighted, pre .line.diff-add, pre .line.diff-remove { display: inline-block; min-width: 100%; }
+pre .line.highlighted { background: #fff5b1; }
+pre .line.diff-add { background: #dafbe1; }
+pre .line.diff-remove { background: #ffebe9; }
+"#;
+
+/// Book metadata read from the header of the `index.adoc` of a section.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub language: String,
+    /// Path of the cover image relative to the section, from `:front-cover-image:`.
+    pub cover: Option<String>,
+}
+
+impl Metadata {
+    /// Reads the document title, the author line or `:author:`, `:lang:` and
+    /// `:front-cover-image:`, which may be an `image:` macro.
+    pub fn from_adoc(content: &str) -> Self {
+        let attributes = adoc::header_attributes(content);
+        let mut lines = content
+            .lines()
+            .skip_while(|line| !line.starts_with("= "))
+            .map(str::trim);
+        let title = lines
+            .next()
+            .and_then(|line| line.strip_prefix("= "))
+            .unwrap_or_default()
+            .to_string();
+        let author_line = lines
+            .next()
+            .filter(|line| !line.is_empty() && !line.starts_with(':') && !line.starts_with("//"));
+        let authors = match (attributes.get("author"), author_line) {
+            (Some(author), _) => vec![author.clone()],
+            (None, Some(line)) => line
+                .split(';')
+                .map(|author| {
+                    regex!(r"<[^>]*>")
+                        .replace_all(author, "")
+                        .trim()
+                        .to_string()
+                })
+                .filter(|author| !author.is_empty())
+                .collect(),
+            (None, None) => Vec::new(),
+        };
+        let cover = attributes.get("front-cover-image").map(|cover| {
+            let cover = cover.strip_prefix("image:").unwrap_or(cover);
+            let cover = cover.split('[').next().unwrap_or_default();
+            cover.trim_start_matches(':').to_string()
+        });
+
+        Self {
+            title,
+            authors,
+            language: attributes
+                .get("lang")
+                .cloned()
+                .unwrap_or_else(|| "en".into()),
+            cover: cover.filter(|cover| !cover.is_empty()),
+        }
+    }
+}
+
+/// Serializes an HTML fragment as XHTML: void elements are closed, text and attributes are
+/// escaped for XML, comments are dropped and SVG and MathML get their namespace.
+pub fn to_xhtml(html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+    let mut res = String::with_capacity(html.len());
+    write_children(fragment.root_element(), &mut res);
+    res
+}
+
+fn write_children(parent: ElementRef, res: &mut String) {
+    for node in parent.children() {
+        match node.value() {
+            Node::Text(text) => res.push_str(&escape(text)),
+            Node::Element(_) => write_element(ElementRef::wrap(node).unwrap(), res),
+            _ => {}
+        }
+    }
+}
+
+fn write_element(node: ElementRef, res: &mut String) {
+    let element = node.value();
+    let name = element.name();
+    res.push('<');
+    res.push_str(name);
+    match name {
+        "svg" => res.push_str(
+            r#" xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink""#,
+        ),
+        "math" => res.push_str(r#" xmlns="http://www.w3.org/1998/Math/MathML""#),
+        _ => {}
+    }
+    for (attr, value) in &element.attrs {
+        if attr.local.as_ref() == "xmlns" || attr.prefix.as_deref() == Some("xmlns") {
+            continue;
+        }
+        res.push(' ');
+        if let Some(prefix) = &attr.prefix {
+            res.push_str(prefix);
+            res.push(':');
+        }
+        res.push_str(&attr.local);
+        res.push_str(&format!(r#"="{}""#, escape(value)));
+    }
+    if VOID_ELEMENTS.contains(&name) {
+        res.push_str("/>");
+        return;
+    }
+    res.push('>');
+    write_children(node, res);
+    res.push_str(&format!("</{name}>"));
+}
+
+fn media_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1.to_lowercase();
+    Some(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "css" => "text/css",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => return None,
+    })
+}
+
+/// Points a link to an anchor of the book at the chapter file holding it.
+fn chapter_link(url: &str) -> Option<String> {
+    let target = url.strip_prefix('#')?;
+    let chapter = target.split("--").next()?;
+    if !chapter.starts_with("chapter-") {
+        return None;
+    }
+    Some(match chapter == target {
+        true => format!("{chapter}.xhtml"),
+        false => format!("{chapter}.xhtml#{target}"),
+    })
+}
+
+/// Local files referenced by `xhtml`, relative to the book and without `..`.
+fn resource_paths(xhtml: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    for caps in regex!(r##"\s(?:src|href|data)="([^"#?]+)"##).captures_iter(xhtml) {
+        let url = caps[1].replace("&amp;", "&");
+        if regex!(r"^(\w[\w+.-]*:|//|/)").is_match(&url) || url.ends_with(".xhtml") {
+            continue;
+        }
+        let Ok(url) = urlencoding::decode(&url) else {
+            continue;
+        };
+        let path = normalize(Path::new(url.as_ref()));
+        if path.starts_with("..") {
+            continue;
+        }
+        let path = path.to_string_lossy().into_owned();
+        if !res.contains(&path) {
+            res.push(path);
+        }
+    }
+    res
+}
+
+/// An EPUB 3 container for a book.
+#[derive(Debug, Default)]
+pub struct Epub {
+    pub metadata: Metadata,
+    /// Chapter files and their XHTML documents, in reading order.
+    pub chapters: Vec<(String, String)>,
+    /// Navigation document listing the chapters and their sections.
+    pub nav: String,
+    /// Files of the book other than the chapters, keyed by their path in the container.
+    pub resources: BTreeMap<String, Vec<u8>>,
+    /// Path of the cover image among the resources.
+    pub cover: Option<String>,
+}
+
+impl Epub {
+    /// Turns the chapters of `book` into XHTML documents. Resources are added separately, the
+    /// ones referenced are listed by [`Epub::resource_paths`].
+    pub fn new(metadata: Metadata, book: &Book, css: &str) -> Self {
+        let lang = escape(&metadata.language);
+        let document = |title: &str, body: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{lang}" lang="{lang}">
+<head>
+<meta charset="UTF-8"/>
+<title>{title}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#
+            )
+        };
+        let relink = |html: &str| {
+            regex!(r#"\shref="(#[^"]*)""#)
+                .replace_all(html, |caps: &lazy_regex::Captures| {
+                    match chapter_link(&caps[1]) {
+                        Some(url) => format!(r#" href="{url}""#),
+                        None => caps[0].to_string(),
+                    }
+                })
+                .into_owned()
+        };
+
+        let mut chapters = Vec::with_capacity(book.chapters.len());
+        let mut toc = String::new();
+        for chapter in &book.chapters {
+            let file = format!("{}.xhtml", chapter.anchor);
+            let title = to_xhtml(&chapter.title);
+            let plain_title = regex!(r"<[^>]*>").replace_all(&title, "");
+            let body = format!(
+                r#"<section id="{}" epub:type="chapter">
+<h1>{title}</h1>
+{}
+</section>"#,
+                chapter.anchor,
+                relink(&to_xhtml(&chapter.content))
+            );
+            chapters.push((file.clone(), document(&plain_title, &body)));
+
+            toc.push_str(&format!(r#"<li><a href="{file}">{title}</a>"#));
+            if !chapter.sections.is_empty() {
+                toc.push_str("<ol>");
+                for section in &chapter.sections {
+                    let url = chapter_link(&section.url).unwrap_or(file.clone());
+                    let title = escape(&section.title);
+                    toc.push_str(&format!(r#"<li><a href="{url}">{title}</a></li>"#));
+                }
+                toc.push_str("</ol>");
+            }
+            toc.push_str("</li>\n");
+        }
+        let nav = document(
+            "Contents",
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{toc}</ol>\n</nav>"
+            ),
+        );
+
+        let mut resources = BTreeMap::new();
+        resources.insert("style.css".to_string(), css.as_bytes().to_vec());
+        Self {
+            metadata,
+            chapters,
+            nav,
+            resources,
+            cover: None,
+        }
+    }
+
+    /// Local files referenced by the chapters and missing from the resources.
+    pub fn resource_paths(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        for (_, xhtml) in &self.chapters {
+            for path in resource_paths(xhtml) {
+                if !res.contains(&path) && !self.resources.contains_key(&path) {
+                    res.push(path);
+                }
+            }
+        }
+        res
+    }
+
+    /// Identifier of the book, derived from its title and authors so that it is stable across
+    /// exports.
+    fn identifier(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.metadata.title);
+        for author in &self.metadata.authors {
+            hasher.update(author);
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        format!(
+            "urn:uuid:{}-{}-{}-{}-{}",
+            &hash[..8],
+            &hash[8..12],
+            &hash[12..16],
+            &hash[16..20],
+            &hash[20..32]
+        )
+    }
+
+    fn package_document(&self) -> String {
+        let metadata = &self.metadata;
+        let mut meta = format!(
+            "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n",
+            self.identifier(),
+            escape(&metadata.title),
+            escape(&metadata.language)
+        );
+        for author in &metadata.authors {
+            meta.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+        }
+        meta.push_str(&format!(
+            "<meta property=\"dcterms:modified\">{}</meta>\n",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+        ));
+
+        let mut manifest = String::from(
+            "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+        );
+        let mut spine = String::new();
+        for (idx, (file, xhtml)) in self.chapters.iter().enumerate() {
+            let properties = match xhtml.contains("<svg") || xhtml.contains("<math") {
+                true => {
+                    let mut properties = Vec::new();
+                    if xhtml.contains("<svg") {
+                        properties.push("svg");
+                    }
+                    if xhtml.contains("<math") {
+                        properties.push("mathml");
+                    }
+                    format!(" properties=\"{}\"", properties.join(" "))
+                }
+                false => String::new(),
+            };
+            manifest.push_str(&format!(
+                "<item id=\"chapter-{idx}\" href=\"{}\" media-type=\"application/xhtml+xml\"{properties}/>\n",
+                escape(file)
+            ));
+            spine.push_str(&format!("<itemref idref=\"chapter-{idx}\"/>\n"));
+        }
+        for (idx, path) in self.resources.keys().enumerate() {
+            let Some(media_type) = media_type(path) else {
+                continue;
+            };
+            let properties = match self.cover.as_ref() == Some(path) {
+                true => " properties=\"cover-image\"",
+                false => "",
+            };
+            manifest.push_str(&format!(
+                "<item id=\"res-{idx}\" href=\"{}\" media-type=\"{media_type}\"{properties}/>\n",
+                escape(path)
+            ));
+        }
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{}">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{meta}</metadata>
+<manifest>
+{manifest}</manifest>
+<spine>
+{spine}</spine>
+</package>
+"#,
+            escape(&metadata.language)
+        )
+    }
+
+    /// Writes the container: the uncompressed `mimetype` first, then the package document, the
+    /// navigation document, the chapters and the resources.
+    pub fn write(&self) -> anyhow::Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#,
+        )?;
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        zip.write_all(self.package_document().as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        zip.write_all(self.nav.as_bytes())?;
+        for (file, xhtml) in &self.chapters {
+            zip.start_file(format!("OEBPS/{file}"), deflated)?;
+            zip.write_all(xhtml.as_bytes())?;
+        }
+        for (path, content) in &self.resources {
+            if media_type(path).is_none() {
+                continue;
+            }
+            zip.start_file(format!("OEBPS/{path}"), deflated)?;
+            zip.write_all(content)?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+/// Exports the pages of `section` as `book.epub` in its output directory, with the metadata of
+/// its `index.adoc`. Returns the file written.
+pub async fn export(generator: &AdocGenerator, section: &str) -> anyhow::Result<PathBuf> {
+    let SectionPages {
+        source_dir,
+        dest_dir,
+        pages,
+        ..
+    } = load_section(generator, section).await?;
+    let book = Book::new(&pages);
+    let index = fs::read_to_string(source_dir.join("index.adoc")).await?;
+    let mut metadata = Metadata::from_adoc(&index);
+    if metadata.title.is_empty() {
+        metadata.title = regex!(r"<[^>]*>").replace_all(&book.title, "").into();
+    }
+
+    let config = generator.config();
+    let css = match config.build_highlighting() {
+        true => format!(
+            "{STYLE}{}",
+            highlight::print_css(&config.highlight).unwrap_or_default()
+        ),
+        false => STYLE.into(),
+    };
+    let cover = metadata.cover.clone();
+    let mut epub = Epub::new(metadata, &book, &css);
+    for path in epub.resource_paths() {
+        match fs::read(dest_dir.join(&path)).await {
+            Ok(content) => {
+                epub.resources.insert(path, content);
+            }
+            Err(err) => warn!("Leave {path} out of the book: {err}"),
+        }
+    }
+    if let Some(cover) = cover {
+        match fs::read(source_dir.join(&cover)).await {
+            Ok(content) => {
+                let extension = cover.rsplit_once('.').map(|item| item.1).unwrap_or("png");
+                let path = format!("cover.{extension}");
+                epub.resources.insert(path.clone(), content);
+                epub.cover = Some(path);
+            }
+            Err(err) => warn!("Read cover {cover} failed: {err}"),
+        }
+    }
+
+    let file = dest_dir.join("book.epub");
+    fs::write(&file, epub.write()?).await?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::{to_xhtml, Epub, Metadata};
+    use crate::{
+        book::{Book, Chapter},
+        site::Link,
+    };
+
+    #[test]
+    fn test_metadata() {
+        let adoc = "// comment\n= My Book\nJane Doe <jane@example.com>; John Roe\n:lang: zh\n:front-cover-image: image:images/cover.png[Cover]\n\nBody\n";
+        assert_eq!(
+            Metadata::from_adoc(adoc),
+            Metadata {
+                title: "My Book".into(),
+                authors: vec!["Jane Doe".into(), "John Roe".into()],
+                language: "zh".into(),
+                cover: Some("images/cover.png".into()),
+            }
+        );
+        let metadata = Metadata::from_adoc("= Other\n:author: Ann\n");
+        assert_eq!(metadata.authors, vec!["Ann".to_string()]);
+        assert_eq!(metadata.language, "en");
+        assert_eq!(metadata.cover, None);
+    }
+
+    #[test]
+    fn test_to_xhtml() {
+        assert_eq!(
+            to_xhtml(
+                r#"<p>a&nbsp;&amp; b<br><img src="a.png" alt="x &quot;y&quot;"><!-- c --></p><svg viewBox="0 0 1 1"><path d="M0"/></svg>"#
+            ),
+            "<p>a\u{a0}&amp; b<br/><img alt=\"x &quot;y&quot;\" src=\"a.png\"/></p><svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" viewBox=\"0 0 1 1\"><path d=\"M0\"></path></svg>"
+        );
+    }
+
+    #[test]
+    fn test_epub() {
+        let book = Book {
+            title: "Book".into(),
+            chapters: vec![
+                Chapter {
+                    anchor: "chapter-index".into(),
+                    title: "Book".into(),
+                    sections: vec![],
+                    content: r##"<p><a href="#chapter-ch1--_a">A</a><img src="ch1/a.png"></p>"##
+                        .into(),
+                },
+                Chapter {
+                    anchor: "chapter-ch1".into(),
+                    title: "One".into(),
+                    sections: vec![Link {
+                        title: "A".into(),
+                        url: "#chapter-ch1--_a".into(),
+                    }],
+                    content: r#"<h2 id="chapter-ch1--_a">A</h2>"#.into(),
+                },
+            ],
+        };
+        let metadata = Metadata {
+            title: "Book & Co".into(),
+            authors: vec!["Jane".into()],
+            language: "en".into(),
+            cover: None,
+        };
+        let mut epub = Epub::new(metadata, &book, "");
+        assert_eq!(epub.resource_paths(), vec!["ch1/a.png".to_string()]);
+        epub.resources.insert("ch1/a.png".into(), vec![1, 2, 3]);
+        epub.resources.insert("cover.jpg".into(), vec![4]);
+        epub.cover = Some("cover.jpg".into());
+        assert!(epub.chapters[0]
+            .1
+            .contains(r#"<a href="chapter-ch1.xhtml#chapter-ch1--_a">A</a>"#));
+        assert!(epub
+            .nav
+            .contains(r#"<li><a href="chapter-ch1.xhtml">One</a><ol><li><a href="chapter-ch1.xhtml#chapter-ch1--_a">A</a></li></ol></li>"#));
+
+        let data = epub.write().unwrap();
+        assert_eq!(&data[30..38], b"mimetype");
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        assert!(opf.contains("<dc:title>Book &amp; Co</dc:title>"), "{opf}");
+        assert!(
+            opf.contains(r#"href="ch1/a.png" media-type="image/png""#),
+            "{opf}"
+        );
+        assert!(
+            opf.contains(r#"media-type="image/jpeg" properties="cover-image""#),
+            "{opf}"
+        );
+        assert!(opf.contains(r#"<itemref idref="chapter-1"/>"#), "{opf}");
+        assert!(zip.by_name("OEBPS/chapter-ch1.xhtml").is_ok());
+        assert!(zip.by_name("OEBPS/nav.xhtml").is_ok());
+    }
+}
@@ -4,4 +4,8 @@ use thiserror::Error;
 pub enum Error {
     #[error("File not found: {0}")]
     NotFound(String),
+    #[error("Invalid theme `{0}`: {1}")]
+    InvalidTheme(String, String),
+    #[error("Failed to render page {page}: {detail}")]
+    Template { page: String, detail: String },
 }
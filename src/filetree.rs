@@ -1,8 +1,21 @@
+use flate2::write::GzEncoder;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+};
 use tokio::fs;
 use tracing::*;
 
+use crate::{
+    config::{self, Compression, FileTreeFormat},
+    site::{IndexNode, Page, Site},
+    source::SourceFormat,
+    summary::Summary,
+};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileTreeNode {
     pub name: String,
@@ -12,160 +25,770 @@ pub struct FileTreeNode {
     pub is_directory: bool,
     pub children: Vec<FileTreeNode>,
     pub level: usize,
+    pub last_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub summary: Summary,
+    /// File holding the children of a section when the tree is sharded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileTreeData {
     pub root: Vec<FileTreeNode>,
-    pub flat_list: Vec<FileTreeNode>,
+    /// `root` as a template value, converted once and shared by every page.
+    #[serde(skip)]
+    value: OnceLock<minijinja::Value>,
 }
 
-impl FileTreeData {
-    pub async fn generate(content_dir: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut root = Vec::new();
-        let mut flat_list = Vec::new();
+/// Node of the compact format, where the tree is stored as a depth-first array.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompactNode {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_directory: bool,
+    /// Index of the parent in `nodes`, absent for top-level nodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub summary: Summary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
+}
 
-        let content_path = Path::new(content_dir);
-        if !content_path.exists() {
-            warn!("Content directory doesn't exist: {}", content_dir);
-            return Ok(FileTreeData { root, flat_list });
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CompactTree {
+    pub nodes: Vec<CompactNode>,
+}
+
+impl CompactTree {
+    pub fn new(root: &[FileTreeNode]) -> Self {
+        let mut res = Self::default();
+        res.push(root, None);
+        res
+    }
+
+    fn push(&mut self, nodes: &[FileTreeNode], parent: Option<usize>) {
+        for node in nodes {
+            let idx = self.nodes.len();
+            self.nodes.push(CompactNode {
+                name: node.name.clone(),
+                title: node.title.clone(),
+                path: node.path.clone(),
+                url: node.url.clone(),
+                is_directory: node.is_directory,
+                parent,
+                last_modified: node.last_modified.clone(),
+                tags: node.tags.clone(),
+                summary: node.summary.clone(),
+                shard: node.shard.clone(),
+            });
+            self.push(&node.children, Some(idx));
         }
+    }
+}
 
-        scan_directory(content_path, &mut root, &mut flat_list, 0).await?;
+/// Per-directory `nav.toml`, overriding the order coming from the index file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct NavConfig {
+    /// Entries listed first, in this order.
+    pub order: Vec<String>,
+    /// Entries left out of the tree.
+    pub hidden: Vec<String>,
+}
 
-        // Sort children by name (directories first, then files)
-        Self::sort_tree_nodes(&mut root);
+impl NavConfig {
+    fn load(dir: &Path) -> Self {
+        let path = dir.join("nav.toml");
+        match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|err| {
+                warn!("Invalid {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// A file or directory to be placed in the tree.
+struct Entry<'a> {
+    name: String,
+    path: PathBuf,
+    is_directory: bool,
+    /// Index node of the entry, when it is part of the xref graph.
+    index: Option<&'a IndexNode>,
+    /// Files under a directory entry that are referenced by the index of an ancestor.
+    nested: Vec<&'a IndexNode>,
+}
 
-        Ok(FileTreeData { root, flat_list })
+impl FileTreeData {
+    pub fn new(root: Vec<FileTreeNode>) -> Self {
+        Self {
+            root,
+            value: OnceLock::new(),
+        }
     }
 
-    fn sort_tree_nodes(nodes: &mut Vec<FileTreeNode>) {
-        nodes.sort_by(|a, b| {
-            // Directories first, then files
-            match (a.is_directory, b.is_directory) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.cmp(&b.name),
-            }
-        });
+    /// The tree passed to templates as `nav`.
+    pub fn template_value(&self) -> minijinja::Value {
+        self.value
+            .get_or_init(|| minijinja::Value::from_serialize(&self.root))
+            .clone()
+    }
+
+    /// Paths of the directories to expand for the page at `path`: those containing it, and its
+    /// own node when it is a directory index.
+    pub fn expanded(&self, path: &str) -> Vec<String> {
+        let mut res = Vec::new();
+        Self::collect_expanded(&self.root, path, &mut res);
+        res
+    }
 
-        // Recursively sort children
+    fn collect_expanded(nodes: &[FileTreeNode], path: &str, res: &mut Vec<String>) -> bool {
+        let mut found = false;
         for node in nodes {
-            Self::sort_tree_nodes(&mut node.children);
+            let active = node.url.as_deref() == Some(path);
+            let contains = Self::collect_expanded(&node.children, path, res);
+            if contains || (active && node.is_directory) {
+                res.push(node.path.clone());
+            }
+            found |= active || contains;
         }
+        found
+    }
+
+    /// Number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        fn count(nodes: &[FileTreeNode]) -> usize {
+            nodes.iter().map(|node| 1 + count(&node.children)).sum()
+        }
+        count(&self.root)
+    }
+
+    /// Builds the tree from the xref graph of the site, so entries follow the order of the index
+    /// files. Directories and files that are not referenced by any index are appended from the
+    /// file system. Titles and dates come from the page registry, so that unbuilt files have
+    /// neither title nor URL.
+    pub fn generate(content_dir: &str, site: &Site) -> Self {
+        let content_path = Path::new(content_dir);
+        if !content_path.exists() {
+            warn!("Content directory doesn't exist: {}", content_dir);
+            return Self::new(Vec::new());
+        }
+
+        let builder = TreeBuilder { content_path, site };
+        let xrefs: Vec<_> = site
+            .index()
+            .into_iter()
+            .flat_map(|index| &index.children)
+            .collect();
+        Self::new(builder.build_dir(content_path, &xrefs, 0))
     }
 }
 
-// Use Box::pin to handle async recursion safely
-fn scan_directory<'a>(
-    dir_path: &'a Path,
-    nodes: &'a mut Vec<FileTreeNode>,
-    flat_list: &'a mut Vec<FileTreeNode>,
-    level: usize,
-) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error>>> + 'a>>
-{
-    Box::pin(async move {
-        let mut entries = fs::read_dir(dir_path).await?;
-
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            let file_name = path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("")
-                .to_string();
+struct TreeBuilder<'a> {
+    content_path: &'a Path,
+    site: &'a Site,
+}
 
-            // Skip hidden files and directories
-            if file_name.starts_with('.') {
+impl<'a> TreeBuilder<'a> {
+    /// Nodes of `dir`, starting with the referenced files in `xrefs`. Files referenced from
+    /// deeper directories are grouped under the subdirectory of `dir` containing them.
+    fn build_dir(&self, dir: &Path, xrefs: &[&'a IndexNode], level: usize) -> Vec<FileTreeNode> {
+        let mut entries: Vec<Entry> = Vec::new();
+        for &child in xrefs {
+            let path = PathBuf::from(&child.source_file);
+            let subdir = path
+                .strip_prefix(dir)
+                .ok()
+                .filter(|relative| relative.components().count() > 1)
+                .and_then(|relative| match relative.components().next()? {
+                    Component::Normal(first) => Some(dir.join(first)),
+                    _ => None,
+                });
+            let Some(subdir) = subdir else {
+                let Some(name) = path.file_name() else {
+                    continue;
+                };
+                entries.push(Entry {
+                    name: name.to_string_lossy().into(),
+                    path,
+                    is_directory: false,
+                    index: Some(child),
+                    nested: Vec::new(),
+                });
                 continue;
+            };
+
+            let is_index = path.parent() == Some(subdir.as_path()) && path.ends_with("index.adoc");
+            let pos = match entries
+                .iter()
+                .position(|entry| entry.is_directory && entry.path == subdir)
+            {
+                Some(pos) => pos,
+                None => {
+                    entries.push(Entry {
+                        name: subdir
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into(),
+                        path: subdir,
+                        is_directory: true,
+                        index: None,
+                        nested: Vec::new(),
+                    });
+                    entries.len() - 1
+                }
+            };
+            let entry = &mut entries[pos];
+            if is_index {
+                entry.index.get_or_insert(child);
+            } else {
+                entry.nested.push(child);
             }
+        }
+        entries.extend(self.scan_dir(dir, &entries));
 
-            let relative_path = path
-                .strip_prefix("content")
-                .unwrap_or(&path)
+        let nav = NavConfig::load(dir);
+        entries.retain(|entry| {
+            !nav.hidden.contains(&entry.name)
+                && self
+                    .attribute(entry, "nav-hidden")
+                    .is_none_or(|v| v == "false")
+        });
+        // Stable sort: pinned entries first, then by `:nav-order:`, then in index order.
+        entries.sort_by_key(
+            |entry| match nav.order.iter().position(|item| *item == entry.name) {
+                Some(pos) => (0, pos as i64),
+                None => match self
+                    .attribute(entry, "nav-order")
+                    .and_then(|v| v.parse::<i64>().ok())
+                {
+                    Some(order) => (1, order),
+                    None => (2, 0),
+                },
+            },
+        );
+
+        let mut nodes = Vec::new();
+        for entry in entries {
+            let relative_path = entry
+                .path
+                .strip_prefix(self.content_path)
+                .unwrap_or(&entry.path)
                 .to_string_lossy()
                 .to_string();
 
-            if path.is_dir() {
-                let mut children = Vec::new();
-                scan_directory(&path, &mut children, flat_list, level + 1).await?;
-
-                let node = FileTreeNode {
-                    name: file_name,
-                    title: None,
-                    path: relative_path.clone(),
-                    url: None,
+            let node = if entry.is_directory {
+                let index_file = entry.path.join("index.adoc");
+                let xrefs: Vec<_> = entry
+                    .index
+                    .into_iter()
+                    .flat_map(|index| &index.children)
+                    .chain(entry.nested)
+                    .collect();
+                let children = self.build_dir(&entry.path, &xrefs, level + 1);
+                let page = index_file.to_str().and_then(|file| self.site.page(file));
+                if children.is_empty() && page.is_none() {
+                    continue;
+                }
+                FileTreeNode {
+                    name: entry.name,
+                    title: page.map(|page| page.title.clone()),
+                    path: relative_path,
+                    url: page.map(|page| page.path.clone()),
                     is_directory: true,
                     children,
                     level,
-                };
-
-                flat_list.push(node.clone());
-                nodes.push(node);
-            } else if path.extension().and_then(|s| s.to_str()) == Some("adoc") {
-                let title = extract_title(&path).await;
-                let url = Some(relative_path.replace(".adoc", ".html"));
-
-                let node = FileTreeNode {
-                    name: file_name,
-                    title,
+                    last_modified: page.and_then(|page| page.last_modified.clone()),
+                    tags: page.map(Page::tags).unwrap_or_default(),
+                    summary: page.map(|page| page.summary.clone()).unwrap_or_default(),
+                    shard: None,
+                }
+            } else {
+                let page = entry.path.to_str().and_then(|file| self.site.page(file));
+                FileTreeNode {
+                    name: entry.name,
+                    title: page.map(|page| page.title.clone()),
                     path: relative_path,
-                    url,
+                    url: page.map(|page| page.path.clone()),
                     is_directory: false,
                     children: Vec::new(),
                     level,
-                };
+                    last_modified: page.and_then(|page| page.last_modified.clone()),
+                    tags: page.map(Page::tags).unwrap_or_default(),
+                    summary: page.map(|page| page.summary.clone()).unwrap_or_default(),
+                    shard: None,
+                }
+            };
 
-                flat_list.push(node.clone());
-                nodes.push(node);
-            }
+            nodes.push(node);
         }
 
-        Ok(())
-    })
-}
+        nodes
+    }
+
+    /// Entries of `dir` on the file system that are not already in `known`, directories first.
+    fn scan_dir(&self, dir: &Path, known: &[Entry]) -> Vec<Entry<'a>> {
+        let known: HashSet<&Path> = known.iter().map(|entry| entry.path.as_path()).collect();
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
 
-async fn extract_title(file_path: &Path) -> Option<String> {
-    match fs::read_to_string(file_path).await {
-        Ok(content) => {
-            // Extract title from first line starting with '='
-            for line in content.lines() {
-                let trimmed = line.trim();
-                if trimmed.starts_with('=') && !trimmed.starts_with("==") {
-                    // Remove the '=' and trim whitespace
-                    let title = trimmed.trim_start_matches('=').trim();
-                    if !title.is_empty() {
-                        return Some(title.to_string());
-                    }
+        let mut res: Vec<Entry> = read_dir
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| !known.contains(path.as_path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?.to_string();
+                // Skip hidden files and directories
+                if name.starts_with('.') || name == "index.adoc" {
+                    return None;
                 }
-                // Stop at first non-empty, non-comment line that doesn't start with ':'
-                if !trimmed.is_empty() && !trimmed.starts_with(':') && !trimmed.starts_with("//") {
-                    break;
+                let is_directory = path.is_dir();
+                if !is_directory && SourceFormat::from_path(&path).is_none() {
+                    return None;
                 }
+                Some(Entry {
+                    name,
+                    path,
+                    is_directory,
+                    index: None,
+                    nested: Vec::new(),
+                })
+            })
+            .collect();
+
+        res.sort_by(|a, b| {
+            // Directories first, then files
+            match (a.is_directory, b.is_directory) {
+                (true, false) => std::cmp::Ordering::Less,
+                (false, true) => std::cmp::Ordering::Greater,
+                _ => a.name.cmp(&b.name),
             }
-            None
+        });
+        res
+    }
+
+    /// Header attribute of the page an entry stands for; directories use their index page.
+    fn attribute(&self, entry: &Entry, name: &str) -> Option<&'a str> {
+        let file = if entry.is_directory {
+            entry.path.join("index.adoc")
+        } else {
+            entry.path.clone()
+        };
+        self.site
+            .page(file.to_str()?)?
+            .attributes
+            .get(name)
+            .map(String::as_str)
+    }
+}
+
+fn compress(data: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            encoder.finish()
         }
-        Err(err) => {
-            warn!("Failed to read file {}: {}", file_path.display(), err);
-            None
+        Compression::Br => {
+            let mut res = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut res, 4096, 11, 22);
+                encoder.write_all(data)?;
+            }
+            Ok(res)
         }
     }
 }
 
-pub async fn generate_filetree_json(
-    content_dir: &str,
-    output_path: &str,
+async fn write_tree(
+    output_dir: &Path,
+    file: &str,
+    nodes: Vec<FileTreeNode>,
+    config: &config::FileTree,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    debug!("Generating file tree from: {}", content_dir);
-
-    let filetree_data = FileTreeData::generate(content_dir).await?;
-    let json_content = serde_json::to_string_pretty(&filetree_data)?;
+    let json_content = match config.format {
+        FileTreeFormat::Nested => serde_json::to_vec(&FileTreeData::new(nodes))?,
+        FileTreeFormat::Compact => serde_json::to_vec(&CompactTree::new(&nodes))?,
+    };
 
+    let output_path = output_dir.join(file);
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    for compression in [Compression::Gzip, Compression::Br] {
+        let extension = match compression {
+            Compression::Gzip => "gz",
+            Compression::Br => "br",
+        };
+        let compressed_path = format!("{}.{extension}", output_path.display());
+        if config.compress.contains(&compression) {
+            fs::write(compressed_path, compress(&json_content, compression)?).await?;
+        } else if Path::new(&compressed_path).exists() {
+            // Left over from a build with this compression enabled.
+            fs::remove_file(compressed_path).await?;
+        }
+    }
     fs::write(output_path, json_content).await?;
-
-    info!(
-        "File tree generated: {} nodes",
-        filetree_data.flat_list.len()
-    );
     Ok(())
 }
+
+/// Writes `filetree.json` to `output_dir`, along with a file per top-level section when sharding
+/// is enabled. Returns the number of nodes. Shards and compressed files of a previous build that
+/// are no longer produced are removed.
+pub async fn generate_filetree_json(
+    tree: &FileTreeData,
+    output_dir: &str,
+    config: &config::FileTree,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let output_dir = Path::new(output_dir);
+    let mut root = tree.root.clone();
+    let total = tree.node_count();
+
+    let shard_dir = output_dir.join("filetree");
+    if shard_dir.exists() {
+        fs::remove_dir_all(&shard_dir).await?;
+    }
+    if config.shard {
+        for node in root.iter_mut() {
+            if !node.is_directory || node.children.is_empty() {
+                continue;
+            }
+            let file = format!("filetree/{}.json", node.path);
+            let children = std::mem::take(&mut node.children);
+            write_tree(output_dir, &file, children, config).await?;
+            node.shard = Some(file);
+        }
+    }
+    write_tree(output_dir, "filetree.json", root, config).await?;
+
+    info!("File tree generated: {} nodes", total);
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{generate_filetree_json, CompactTree, FileTreeData, FileTreeNode};
+    use crate::{
+        config::{self, Compression},
+        site::{IndexNode, Page, Site},
+        url::UrlBuilder,
+    };
+
+    fn page(source_file: &str, title: &str, attributes: &[(&str, &str)]) -> Page {
+        Page {
+            source_file: source_file.into(),
+            dest_file: String::new(),
+            path: source_file.replace(".adoc", ".html"),
+            title: title.into(),
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            last_modified: None,
+            summary: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let content = root.join("content");
+        let guide = content.join("guide");
+        std::fs::create_dir_all(&guide).unwrap();
+        for file in [
+            "index.adoc",
+            "guide/index.adoc",
+            "guide/b.adoc",
+            "guide/a.adoc",
+            "guide/draft.adoc",
+            "guide/orphan.adoc",
+            "guide/hidden.adoc",
+        ] {
+            std::fs::write(content.join(file), "= Orphan\n").unwrap();
+        }
+        std::fs::write(guide.join("nav.toml"), "hidden = [\"draft.adoc\"]\n").unwrap();
+
+        let file = |name: &str| content.join(name).to_str().unwrap().to_string();
+        let node = |name: &str, children| IndexNode {
+            source_file: file(name),
+            children,
+        };
+        let index = node(
+            "index.adoc",
+            vec![node(
+                "guide/index.adoc",
+                vec![
+                    node("guide/b.adoc", vec![]),
+                    node("guide/a.adoc", vec![]),
+                    node("guide/draft.adoc", vec![]),
+                    node("guide/hidden.adoc", vec![]),
+                ],
+            )],
+        );
+        let site = Site::new(
+            Some(&index),
+            vec![
+                page(&file("index.adoc"), "Home", &[]),
+                page(&file("guide/index.adoc"), "Guide", &[]),
+                page(&file("guide/b.adoc"), "B", &[]),
+                page(
+                    &file("guide/a.adoc"),
+                    "A",
+                    &[("nav-order", "1"), ("tags", "x, y")],
+                ),
+                page(&file("guide/draft.adoc"), "Draft", &[]),
+                page(&file("guide/hidden.adoc"), "Hidden", &[("nav-hidden", "")]),
+            ],
+            UrlBuilder::default(),
+        );
+
+        let tree = FileTreeData::generate(content.to_str().unwrap(), &site);
+
+        assert_eq!(tree.root.len(), 1);
+        let guide = &tree.root[0];
+        assert!(guide.is_directory);
+        assert_eq!(guide.title.as_deref(), Some("Guide"));
+        assert!(guide.url.is_some());
+
+        let children: Vec<_> = guide
+            .children
+            .iter()
+            .map(|node| (node.name.as_str(), node.url.is_some()))
+            .collect();
+        assert_eq!(
+            children,
+            vec![("a.adoc", true), ("b.adoc", true), ("orphan.adoc", false)]
+        );
+        assert_eq!(guide.children[0].tags, vec!["x", "y"]);
+        assert!(guide.children[2].title.is_none());
+    }
+
+    #[test]
+    fn test_generate_nested_xref() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        std::fs::create_dir_all(content.join("guide")).unwrap();
+        for file in [
+            "index.adoc",
+            "a.adoc",
+            "guide/setup.adoc",
+            "guide/other.adoc",
+        ] {
+            std::fs::write(content.join(file), "= Page\n").unwrap();
+        }
+
+        let file = |name: &str| content.join(name).to_str().unwrap().to_string();
+        let node = |name: &str| IndexNode {
+            source_file: file(name),
+            children: Vec::new(),
+        };
+        let index = IndexNode {
+            children: vec![node("guide/setup.adoc"), node("a.adoc")],
+            ..node("index.adoc")
+        };
+        let site = Site::new(
+            Some(&index),
+            vec![
+                page(&file("index.adoc"), "Home", &[]),
+                page(&file("a.adoc"), "A", &[]),
+                page(&file("guide/setup.adoc"), "Setup", &[]),
+            ],
+            UrlBuilder::default(),
+        );
+
+        let tree = FileTreeData::generate(content.to_str().unwrap(), &site);
+
+        let paths: Vec<_> = tree.root.iter().map(|node| node.path.as_str()).collect();
+        assert_eq!(paths, vec!["guide", "a.adoc"]);
+        let children: Vec<_> = tree.root[0]
+            .children
+            .iter()
+            .map(|node| (node.path.as_str(), node.url.is_some()))
+            .collect();
+        assert_eq!(
+            children,
+            vec![("guide/setup.adoc", true), ("guide/other.adoc", false)]
+        );
+        assert_eq!(tree.node_count(), 4);
+    }
+
+    #[test]
+    fn test_generate_nav_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        std::fs::create_dir_all(&content).unwrap();
+        for file in ["index.adoc", "a.adoc", "b.adoc", "c.adoc"] {
+            std::fs::write(content.join(file), "= Page\n").unwrap();
+        }
+        std::fs::write(
+            content.join("nav.toml"),
+            "order = [\"c.adoc\", \"b.adoc\"]\n",
+        )
+        .unwrap();
+
+        let file = |name: &str| content.join(name).to_str().unwrap().to_string();
+        let node = |name: &str| IndexNode {
+            source_file: file(name),
+            children: Vec::new(),
+        };
+        let index = IndexNode {
+            children: vec![node("a.adoc"), node("b.adoc"), node("c.adoc")],
+            ..node("index.adoc")
+        };
+        let site = Site::new(
+            Some(&index),
+            vec![
+                page(&file("a.adoc"), "A", &[]),
+                page(&file("b.adoc"), "B", &[]),
+                page(&file("c.adoc"), "C", &[("nav-order", "1")]),
+            ],
+            UrlBuilder::default(),
+        );
+
+        let tree = FileTreeData::generate(content.to_str().unwrap(), &site);
+
+        let names: Vec<_> = tree.root.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, vec!["c.adoc", "b.adoc", "a.adoc"]);
+    }
+
+    #[test]
+    fn test_expanded() {
+        let leaf = |name: &str| FileTreeNode {
+            name: name.into(),
+            title: None,
+            path: name.into(),
+            url: Some(format!("{name}.html")),
+            is_directory: false,
+            children: Vec::new(),
+            level: 0,
+            last_modified: None,
+            tags: Vec::new(),
+            summary: Default::default(),
+            shard: None,
+        };
+        let tree = FileTreeData::new(vec![
+            FileTreeNode {
+                is_directory: true,
+                children: vec![leaf("a/1"), leaf("a/2")],
+                ..leaf("a")
+            },
+            leaf("b"),
+        ]);
+
+        assert_eq!(tree.expanded("a/2.html"), vec!["a"]);
+        assert_eq!(tree.expanded("a.html"), vec!["a"]);
+        assert!(tree.expanded("b.html").is_empty());
+        assert!(tree.expanded("c.html").is_empty());
+        assert_eq!(tree.template_value().len(), Some(2));
+    }
+
+    #[test]
+    fn test_compact_tree() {
+        let leaf = |name: &str, level| FileTreeNode {
+            name: name.into(),
+            title: None,
+            path: name.into(),
+            url: Some(format!("{name}.html")),
+            is_directory: false,
+            children: Vec::new(),
+            level,
+            last_modified: None,
+            tags: Vec::new(),
+            summary: Default::default(),
+            shard: None,
+        };
+        let root = vec![
+            FileTreeNode {
+                is_directory: true,
+                children: vec![leaf("a/1", 1), leaf("a/2", 1)],
+                ..leaf("a", 0)
+            },
+            leaf("b", 0),
+        ];
+
+        let tree = CompactTree::new(&root);
+        let parents: Vec<_> = tree
+            .nodes
+            .iter()
+            .map(|node| (node.path.as_str(), node.parent))
+            .collect();
+        assert_eq!(
+            parents,
+            vec![("a", None), ("a/1", Some(0)), ("a/2", Some(0)), ("b", None)]
+        );
+        assert_eq!(FileTreeData::new(root).node_count(), 4);
+
+        let json = serde_json::to_string(&tree.nodes[3]).unwrap();
+        assert_eq!(json, r#"{"name":"b","path":"b","url":"b.html"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_generate_filetree_json() {
+        let leaf = |name: &str| FileTreeNode {
+            name: name.into(),
+            title: None,
+            path: name.into(),
+            url: Some(format!("{name}.html")),
+            is_directory: false,
+            children: Vec::new(),
+            level: 0,
+            last_modified: None,
+            tags: Vec::new(),
+            summary: Default::default(),
+            shard: None,
+        };
+        let tree = FileTreeData::new(vec![
+            FileTreeNode {
+                is_directory: true,
+                children: vec![leaf("a/1")],
+                ..leaf("a")
+            },
+            leaf("b"),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path();
+        let output_dir = output.to_str().unwrap();
+
+        let config = config::FileTree {
+            compress: vec![Compression::Gzip],
+            shard: true,
+            ..Default::default()
+        };
+        let total = generate_filetree_json(&tree, output_dir, &config)
+            .await
+            .unwrap();
+        assert_eq!(total, 3);
+        assert!(output.join("filetree/a.json").exists());
+        assert!(output.join("filetree/a.json.gz").exists());
+        assert!(output.join("filetree.json.gz").exists());
+
+        generate_filetree_json(&tree, output_dir, &config::FileTree::default())
+            .await
+            .unwrap();
+        assert!(!output.join("filetree").exists());
+        assert!(!output.join("filetree.json.gz").exists());
+        let json = std::fs::read_to_string(output.join("filetree.json")).unwrap();
+        let data: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(data.as_object().unwrap().len(), 1);
+        assert_eq!(data["root"][0]["children"][0]["path"], "a/1");
+    }
+}
@@ -1,24 +1,57 @@
-use std::path::{self, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tracing::*;
 
 use crate::{
-    config,
+    assets::{AssetManifest, Resources},
+    book::Book,
+    codeblock,
+    config::{self, Hook},
+    diagrams::Diagrams,
+    error::Error,
+    filetree::FileTreeData,
+    highlight::{self, Highlighter},
+    images::ImageProcessor,
     index::index_insert,
-    utils::{jinjaext, AsciidoctorBuilder, GitInfo, HtmlParser, Tmpl},
+    markdown,
+    math::render_mathml,
+    plugin::{Plugin, Plugins},
+    site::{Navigation, Site},
+    source::SourceFormat,
+    summary::Summary,
+    theme::{Theme, BOOK_LAYOUT},
+    url::UrlBuilder,
+    utils::{jinjaext, AsciidoctorBuilder, HtmlParser, TextFilter, Tmpl},
 };
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub site: String,
+    /// Output path of the page relative to the site root.
+    pub path: String,
+    /// URL of the site root as seen from this page.
+    pub root: String,
     pub title: String,
     pub content: Option<String>,
     pub toc: Option<String>,
     pub footnotes: Option<String>,
     pub last_modify_date: Option<String>,
     pub ancestors: Vec<(String, String)>,
+    #[serde(flatten)]
+    pub navigation: Navigation,
+    /// Paths of the tree nodes to show expanded, the directories containing the current page.
+    pub expanded: Vec<String>,
+    #[serde(flatten)]
+    pub summary: Summary,
+    /// How formulas are rendered, themes load a math renderer for `client`.
+    pub math: config::MathEngine,
+    /// Source blocks are highlighted, and `highlight.css` is among the assets.
+    pub highlight: bool,
 }
 
 #[derive(Debug)]
@@ -33,85 +66,168 @@ pub struct BuildContext {
 pub struct AdocGenerator {
     engine: Tmpl,
     config: config::Config,
+    text_filter: TextFilter,
+    resources: Resources,
+    assets: Arc<AssetManifest>,
+    images: ImageProcessor,
+    diagrams: Diagrams,
+    highlighter: Option<Highlighter>,
+    plugins: Plugins,
 }
 
 impl AdocGenerator {
-    pub fn new(theme_dir: String, config: config::Config) -> Self {
-        let engine = Tmpl::new(theme_dir);
+    pub fn new(
+        theme: &Theme,
+        config: config::Config,
+        urls: UrlBuilder,
+        assets: Arc<AssetManifest>,
+    ) -> Self {
+        let engine = Tmpl::new(theme, urls, assets.clone());
+        let text_filter =
+            TextFilter::new(&config.search.exclude_selectors, config.search.exclude_code);
+        let images = ImageProcessor::new(&config.images, "content", "public", ".cache/images");
+        let diagrams = Diagrams::new(&config.diagrams, "content", "public", ".cache/diagrams");
+        let highlighter = config.build_highlighting().then(Highlighter::new);
+        let plugins = Plugins::from_config(&config.plugins);
+
+        Self {
+            engine,
+            config,
+            text_filter,
+            resources: Resources::new("public"),
+            assets,
+            images,
+            diagrams,
+            highlighter,
+            plugins,
+        }
+    }
 
-        Self { engine, config }
+    /// Adds a plugin run after those of the configuration.
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.register(plugin);
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    pub fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    /// Local files referenced by the pages rendered so far.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// Diagrams of the pages rendered so far.
+    pub fn diagrams(&self) -> &Diagrams {
+        &self.diagrams
     }
 
     pub async fn render_html(
         &self,
         ctx: BuildContext,
         html: String,
-        gitinfo: &GitInfo,
+        site: &Site,
+        tree: &FileTreeData,
         need_minify: bool,
-    ) {
+    ) -> Result<(), Error> {
         let BuildContext {
             source_dir,
             source_file,
             dest_dir,
             dest_file,
         } = ctx;
+        let html = markdown::rewrite_links(&html, Path::new(&source_dir), |file| {
+            site.page(file).is_some()
+        });
+        let html = self
+            .diagrams
+            .process_page(&html, &source_dir, &dest_dir)
+            .await;
+        let image_urls = HtmlParser::new(&html).get_image_urls();
+        let html = self
+            .images
+            .process_page(
+                &html,
+                &image_urls,
+                Path::new(&source_dir),
+                Path::new(&dest_dir),
+            )
+            .await;
         let html = HtmlParser::new(&html);
+        let Some(page) = site.page(&source_file) else {
+            return Err(Error::NotFound(source_file));
+        };
 
-        match dest_file.split_once("public/") {
-            Some((_, p)) => {
-                let _ = index_insert(
-                    p.into(),
-                    (
-                        html.text(),
-                        html.get_title(),
-                        gitinfo.get_last_commit_time_of_file(&source_file).await,
-                    ),
-                );
-            }
-            None => {
-                let _ = index_insert(
-                    dest_file.clone(),
-                    (
-                        html.text(),
-                        html.get_title(),
-                        gitinfo.get_last_commit_time_of_file(&source_file).await,
-                    ),
-                );
-            }
-        }
+        let path = page.path.clone();
+        let _ = index_insert(
+            path.clone(),
+            (
+                page.title.clone(),
+                page.last_modified.clone(),
+                (
+                    page.summary.summary.clone(),
+                    page.summary.word_count as u64,
+                    page.summary.reading_time as u64,
+                ),
+                html.sections(&self.text_filter)
+                    .into_iter()
+                    .map(|item| (item.id, item.heading, item.breadcrumb, item.text))
+                    .collect(),
+            ),
+        );
 
-        let title = html.get_title();
+        let title = page.title.clone();
         if title == "Untitled" {
             warn!("Title is empty, file: {}", source_file);
         }
 
         let document = Document {
             site: self.config.site.clone(),
+            root: site.urls().root(&path),
+            path,
             title,
-            content: html.get_content(),
+            content: html
+                .get_content()
+                .map(|content| codeblock::enrich(&content)),
             toc: html.get_toc(),
             footnotes: html.get_footnotes(),
-            last_modify_date: gitinfo.get_last_commit_time_of_file(&source_file).await,
-            ancestors: Self::generate_paths(&dest_file),
+            last_modify_date: page.last_modified.clone(),
+            ancestors: site.ancestors(&source_file),
+            navigation: site.navigation(&source_file),
+            expanded: tree.expanded(&page.path),
+            summary: page.summary.clone(),
+            math: self.config.math.engine,
+            highlight: self.highlighter.is_some(),
         };
+        let document = self.plugins.document(&source_file, document).await;
+        let root = document.root.clone();
 
-        let document = self.render(&document, need_minify);
+        let document = self
+            .render(&document, tree.template_value(), need_minify)
+            .map_err(|err| Error::Template {
+                page: source_file.clone(),
+                detail: self.engine.describe_error(&err),
+            })?;
+        let document = self.assets.rewrite_html(&document, &root);
+        let document = self.plugins.page_rendered(&source_file, document).await;
         if let Err(err) = fs::write(&dest_file, &document).await {
             eprintln!("Failed write file: {}", err);
         }
 
-        let images = html.get_image_urls();
-        let filtered_images: Vec<_> = images
-            .iter()
-            .filter(|item| !item.starts_with("diag-"))
-            .collect();
-
-        if !filtered_images.is_empty() {
-            let acts = filtered_images
-                .iter()
-                .map(|item| Self::move_assets(item, &source_dir, &dest_dir));
-            futures::future::join_all(acts).await;
+        for url in html.resource_urls() {
+            self.resources.add(
+                &source_file,
+                &url,
+                Path::new(&source_dir),
+                Path::new(&dest_dir),
+            );
         }
+
+        Ok(())
     }
 
     pub async fn generate_html(&self, source_file: PathBuf) -> Option<(BuildContext, String)> {
@@ -120,12 +236,55 @@ impl AdocGenerator {
         };
 
         debug!("Generate file: {} -> {}", ctx.source_file, ctx.dest_file);
-        let html = Self::generate_raw_page(
-            self.config.asciidoc.clone(),
-            ctx.source_file.clone(),
-            ctx.dest_dir.clone(),
-        )
-        .await;
+        let html = match SourceFormat::from_path(&ctx.source_file) {
+            Some(SourceFormat::Markdown) => match fs::read_to_string(&ctx.source_file).await {
+                Ok(content) => {
+                    markdown::render(&self.plugins.source_loaded(&ctx.source_file, content).await)
+                }
+                Err(err) => {
+                    error!("Failed read {}: {err}", ctx.source_file);
+                    return None;
+                }
+            },
+            _ => {
+                let mut asciidoc = self.config.asciidoc.clone();
+                if self.config.math.engine != config::MathEngine::AsciidoctorMathematical {
+                    asciidoc
+                        .extensions
+                        .retain(|item| item != "asciidoctor-mathematical");
+                }
+                // Asciidoctor reads the file itself unless a plugin changes it.
+                let source = match self.plugins.handles(Hook::SourceLoaded) {
+                    true => match fs::read_to_string(&ctx.source_file).await {
+                        Ok(content) => {
+                            Some(self.plugins.source_loaded(&ctx.source_file, content).await)
+                        }
+                        Err(err) => {
+                            error!("Failed read {}: {err}", ctx.source_file);
+                            return None;
+                        }
+                    },
+                    false => None,
+                };
+                Self::generate_raw_page(
+                    asciidoc,
+                    ctx.source_file.clone(),
+                    ctx.dest_dir.clone(),
+                    self.diagrams.out_dir(&ctx.source_dir),
+                    source,
+                )
+                .await
+            }
+        };
+        let html = self.plugins.raw_html(&ctx.source_file, html).await;
+        let html = match self.config.math.engine {
+            config::MathEngine::Mathml => render_mathml(&html),
+            _ => html,
+        };
+        let html = match &self.highlighter {
+            Some(highlighter) => highlighter.highlight_html(&html),
+            None => html,
+        };
 
         Some((ctx, html))
     }
@@ -150,10 +309,12 @@ impl AdocGenerator {
             return Err(());
         }
 
-        let source_file: String = source_file.to_str().unwrap().into();
         let dest_file = source_file
-            .replace("content", "public")
-            .replace(".adoc", ".html");
+            .with_extension("html")
+            .to_str()
+            .unwrap()
+            .replace("content", "public");
+        let source_file: String = source_file.to_str().unwrap().into();
 
         Ok(BuildContext {
             source_dir,
@@ -163,31 +324,27 @@ impl AdocGenerator {
         })
     }
 
-    pub async fn move_assets(item: &str, source: &str, des: &str) {
-        let source_file = path::Path::new(source).join(item);
-        if !source_file.exists() {
-            warn!("File doesn't exists: {}", source_file.display());
-            return;
-        }
-        let des_file = path::Path::new(des).join(item);
-        let des_path = des_file.parent().unwrap();
-        if !des_path.exists() {
-            fs::create_dir_all(des_path).await.unwrap();
-        }
-
-        debug!(
-            "Move file: {} -> {}",
-            source_file.display(),
-            des_file.display()
-        );
-        fs::copy(source_file, des_file).await.unwrap();
-    }
-
     pub async fn generate_raw_page(
         config: config::Asciidoc,
         source_file: String,
         des: String,
+        diagram_dir: PathBuf,
+        source: Option<String>,
     ) -> String {
+        let mut output = Self::builder(&config, source_file, des);
+        output.diagram_dir(diagram_dir.to_string_lossy().into());
+        if let Some(source) = source {
+            output.source(source);
+        }
+        output.build().await
+    }
+
+    /// An asciidoctor run for `source_file` with the attributes and extensions of the site.
+    pub fn asciidoctor(&self, source_file: String, des: String) -> AsciidoctorBuilder {
+        Self::builder(&self.config.asciidoc, source_file, des)
+    }
+
+    fn builder(config: &config::Asciidoc, source_file: String, des: String) -> AsciidoctorBuilder {
         let mut output = AsciidoctorBuilder::new(source_file, des);
         config.attributes.iter().for_each(|(key, value)| {
             match value {
@@ -198,39 +355,55 @@ impl AdocGenerator {
         config.extensions.iter().for_each(|value| {
             output.plugin(value.clone());
         });
-        output.build().await
+        output
     }
 
-    pub fn generate_paths(dest_file: &str) -> Vec<(String, String)> {
-        let paths: Vec<String> = dest_file
-            .replace("public/", "")
-            .split('/')
-            .map(|item| item.to_string())
-            .collect();
-        let mut res: Vec<(String, String)> = Default::default();
-        for idx in 1..paths.len() {
-            let a = &paths[0..idx];
-            let mut v = a.join("/").to_string();
-            v.insert(0, '/');
-            res.push((paths[idx - 1].clone(), v));
-        }
-
-        res
+    /// Renders `book` with the `book` layout of the theme, or the embedded one when the theme has
+    /// none.
+    pub fn render_book(&self, book: &Book) -> Result<String, Error> {
+        let engine = &self.engine.engine;
+        let tmpl = match engine.get_template("book") {
+            Err(err) if err.kind() == minijinja::ErrorKind::TemplateNotFound => {
+                engine.template_from_named_str("book", BOOK_LAYOUT)
+            }
+            res => res,
+        };
+        let highlight_css = match self.config.build_highlighting() {
+            true => highlight::print_css(&self.config.highlight).unwrap_or_default(),
+            false => String::new(),
+        };
+        tmpl.and_then(|tmpl| {
+            tmpl.render(minijinja::context! {
+                site => &self.config.site,
+                title => &book.title,
+                chapters => &book.chapters,
+                highlight_css,
+            })
+        })
+        .map_err(|err| Error::Template {
+            page: book.title.clone(),
+            detail: self.engine.describe_error(&err),
+        })
     }
 
-    pub fn render(&self, context: &Document, need_minify: bool) -> String {
-        let tmpl = self
-            .engine
-            .engine
-            .get_template("page")
-            .inspect_err(|err| error!("Get template `page` failed. {}", err))
-            .unwrap();
-        let ctx = minijinja::value::Value::from_serialize(context);
-        let mut res = tmpl.render(ctx).unwrap();
+    /// Renders the `page` layout with `context` and `nav`, the site tree shared by every page,
+    /// where the node whose `url` is the `path` of the page is the current page.
+    pub fn render(
+        &self,
+        context: &Document,
+        nav: minijinja::Value,
+        need_minify: bool,
+    ) -> Result<String, minijinja::Error> {
+        let tmpl = self.engine.engine.get_template("page")?;
+        let ctx = minijinja::context! {
+            nav,
+            ..minijinja::value::Value::from_serialize(context)
+        };
+        let mut res = tmpl.render(ctx)?;
         if need_minify {
             res = jinjaext::minify(&res);
         }
 
-        res
+        Ok(res)
     }
 }
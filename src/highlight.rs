@@ -0,0 +1,194 @@
+use lazy_regex::regex;
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, line_tokens_to_classed_spans, ClassStyle},
+    parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet},
+};
+use tracing::*;
+
+use crate::{config, utils::unescape};
+
+/// Classes of highlighted tokens start with `hl-`, so that they do not clash with the theme.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Stylesheet of the light theme, followed by the dark theme for `prefers-color-scheme: dark`.
+pub fn theme_css(config: &config::Highlight) -> anyhow::Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let theme = |name: &str| {
+        themes.themes.get(name).ok_or_else(|| {
+            let known: Vec<_> = themes.themes.keys().map(String::as_str).collect();
+            anyhow::anyhow!(
+                "unknown theme `{name}`, expected one of {}",
+                known.join(", ")
+            )
+        })
+    };
+    let light = css_for_theme_with_class_style(theme(&config.light_theme)?, CLASS_STYLE)?;
+    let dark = css_for_theme_with_class_style(theme(&config.dark_theme)?, CLASS_STYLE)?;
+    Ok(format!(
+        "{light}\n@media (prefers-color-scheme: dark) {{\n{dark}\n}}\n"
+    ))
+}
+
+/// Stylesheet of the light theme alone, for documents meant to be printed.
+pub fn print_css(config: &config::Highlight) -> anyhow::Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let theme = themes
+        .themes
+        .get(&config.light_theme)
+        .ok_or_else(|| anyhow::anyhow!("unknown theme `{}`", config.light_theme))?;
+    Ok(css_for_theme_with_class_style(theme, CLASS_STYLE)?)
+}
+
+/// Highlights the source blocks asciidoctor leaves alone when no `source-highlighter` is set.
+#[derive(Debug)]
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Highlighter {
+    pub fn new() -> Self {
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+        }
+    }
+
+    /// Highlights every `<pre class="highlight"><code data-lang="…">` of `html`. Blocks in an
+    /// unknown language, blocks already highlighted, and blocks containing markup other than
+    /// callouts are left as they are.
+    pub fn highlight_html(&self, html: &str) -> String {
+        let re = regex!(
+            r#"(?s)(<pre class="highlight"><code[^>]*\sdata-lang="([^"]+)"[^>]*>)(.*?)(</code></pre>)"#
+        );
+        re.replace_all(html, |caps: &lazy_regex::Captures| {
+            let Some(syntax) = self.syntaxes.find_syntax_by_token(&caps[2]) else {
+                return caps[0].to_string();
+            };
+            match self.highlight_code(syntax, &caps[3]) {
+                Some(code) => format!("{}{code}{}", &caps[1], &caps[4]),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+    }
+
+    /// Every line of the output is balanced: spans still open at the end of a line are closed
+    /// and opened again on the next one, so that lines can be wrapped or marked individually.
+    fn highlight_code(&self, syntax: &SyntaxReference, code: &str) -> Option<String> {
+        let callouts = regex!(
+            r#"(?:\s*(?:<i class="conum" data-value="\d+"></i>)?<b(?: class="conum")?>\(\d+\)</b>)+\s*$"#
+        );
+        let mut state = ParseState::new(syntax);
+        let mut scopes = ScopeStack::new();
+        let mut open: Vec<String> = Vec::new();
+        let mut res = Vec::new();
+        for line in code.split('\n') {
+            let (text, callout) = match callouts.find(line) {
+                Some(item) => (&line[..item.start()], item.as_str()),
+                None => (line, ""),
+            };
+            if text.contains('<') {
+                return None;
+            }
+
+            let text = format!("{}\n", unescape(text));
+            let ops = match state.parse_line(&text, &self.syntaxes) {
+                Ok(ops) => ops,
+                Err(err) => {
+                    warn!("Failed to highlight {} code: {err}", syntax.name);
+                    return None;
+                }
+            };
+            let (spans, _) =
+                line_tokens_to_classed_spans(&text, &ops, CLASS_STYLE, &mut scopes).ok()?;
+
+            let mut out: String = open.concat();
+            let spans = spans.strip_suffix('\n').unwrap_or(&spans);
+            for tag in regex!(r#"<span [^>]*>|</span>|[^<]+"#).find_iter(spans) {
+                match tag.as_str() {
+                    "</span>" => {
+                        open.pop();
+                    }
+                    tag if tag.starts_with("<span ") => open.push(tag.into()),
+                    _ => {}
+                }
+                out.push_str(tag.as_str());
+            }
+            // The newline may be the only token of a scope.
+            out = out.replace('\n', "");
+            out.push_str(&"</span>".repeat(open.len()));
+            out.push_str(callout);
+            res.push(out);
+        }
+        Some(res.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{theme_css, Highlighter};
+    use crate::config;
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    #[test]
+    fn test_highlight_html() {
+        let highlighter = Highlighter::new();
+        let code = "/* a\n   b */\nlet s = \"<\"; <b class=\"conum\">(1)</b>";
+        let html = format!(
+            r#"<pre class="highlight"><code class="language-rust" data-lang="rust">{}</code></pre>"#,
+            escape(code).replace(
+                "&lt;b class=\"conum\"&gt;(1)&lt;/b&gt;",
+                r#"<b class="conum">(1)</b>"#
+            )
+        );
+        let res = highlighter.highlight_html(&html);
+        let lines: Vec<&str> = res.split('\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].contains(r#"<span class="hl-comment hl-block hl-rust">"#),
+            "{res}"
+        );
+        assert!(lines[0].ends_with(" a</span></span>"), "{res}");
+        assert!(
+            lines[1].starts_with(
+                r#"<span class="hl-source hl-rust"><span class="hl-comment hl-block hl-rust">"#
+            ),
+            "{res}"
+        );
+        assert!(lines[2].contains("&lt;"), "{res}");
+        assert!(
+            lines[2].ends_with(r#"</span> <b class="conum">(1)</b></code></pre>"#),
+            "{res}"
+        );
+
+        let unknown = r#"<pre class="highlight"><code data-lang="nope">x</code></pre>"#;
+        assert_eq!(highlighter.highlight_html(unknown), unknown);
+        let marked =
+            r#"<pre class="highlight"><code data-lang="rust"><strong>x</strong></code></pre>"#;
+        assert_eq!(highlighter.highlight_html(marked), marked);
+    }
+
+    #[test]
+    fn test_theme_css() {
+        let css = theme_css(&config::Highlight::default()).unwrap();
+        assert!(css.contains(".hl-comment"));
+        assert!(css.contains("@media (prefers-color-scheme: dark)"));
+
+        let config = config::Highlight {
+            light_theme: "missing".into(),
+            ..Default::default()
+        };
+        assert!(theme_css(&config).is_err());
+    }
+}
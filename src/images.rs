@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use lazy_regex::regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::*;
+
+use crate::{config, site::normalize};
+
+/// Bumped whenever the processing changes, so that cached variants are regenerated.
+const CACHE_VERSION: u32 = 2;
+const RASTER_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+
+/// Image format, file extension and MIME type of the variants in `format`.
+fn encoder(format: config::ImageFormat) -> Option<(ImageFormat, &'static str, &'static str)> {
+    match format {
+        config::ImageFormat::Webp => Some((ImageFormat::WebP, "webp", "image/webp")),
+        #[cfg(feature = "avif")]
+        config::ImageFormat::Avif => Some((ImageFormat::Avif, "avif", "image/avif")),
+        #[cfg(not(feature = "avif"))]
+        config::ImageFormat::Avif => None,
+    }
+}
+
+/// A file generated from an image, or the image itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    pub width: u32,
+    pub extension: String,
+    pub mime: String,
+    /// The original image rather than a generated file.
+    #[serde(default)]
+    pub original: bool,
+}
+
+impl Variant {
+    /// Name of the variant of the image named `file_name`: `shot.png` becomes `shot-480w.webp`.
+    pub fn file_name(&self, file_name: &str) -> String {
+        if self.original {
+            return file_name.into();
+        }
+        let stem = file_name
+            .rsplit_once('.')
+            .map(|(stem, _)| stem)
+            .unwrap_or(file_name);
+        format!("{stem}-{}w.{}", self.width, self.extension)
+    }
+}
+
+/// Intrinsic size of an image and the variants written next to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    /// Grouped by format, the format of the original first, each group by ascending width.
+    pub variants: Vec<Variant>,
+}
+
+impl ProcessedImage {
+    /// Decodes `content` and writes its variants to `output_dir` under their cache names.
+    fn generate(
+        content: &[u8],
+        config: &config::Images,
+        output_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let format = image::guess_format(content)?;
+        let image = image::load_from_memory_with_format(content, format)?;
+        let (width, height) = (image.width(), image.height());
+        // 16 bit and grayscale images are not supported by every encoder.
+        let image = match image.color().has_alpha() {
+            true => DynamicImage::ImageRgba8(image.to_rgba8()),
+            false => DynamicImage::ImageRgb8(image.to_rgb8()),
+        };
+
+        let mut widths: Vec<u32> = config
+            .widths
+            .iter()
+            .copied()
+            .filter(|item| *item > 0 && *item < width)
+            .collect();
+        widths.sort_unstable();
+        widths.dedup();
+        widths.push(width);
+
+        let original = format.extensions_str()[0];
+        let mut formats = vec![(format, original, format.to_mime_type())];
+        formats.extend(
+            config
+                .formats
+                .iter()
+                .filter_map(|item| encoder(*item))
+                .filter(|(item, _, _)| *item != format)
+                // The WebP encoder is lossless only, which makes photos several times larger
+                // than their JPEG.
+                .filter(|(item, _, _)| {
+                    !(*item == ImageFormat::WebP && format == ImageFormat::Jpeg)
+                }),
+        );
+
+        std::fs::create_dir_all(output_dir)?;
+        let mut variants = Vec::new();
+        for (format, extension, mime) in formats {
+            for &variant_width in &widths {
+                let variant = Variant {
+                    width: variant_width,
+                    extension: extension.into(),
+                    mime: mime.into(),
+                    original: variant_width == width && extension == original,
+                };
+                if !variant.original {
+                    let resized = match variant_width == width {
+                        true => image.clone(),
+                        false => {
+                            let variant_height =
+                                (height as u64 * variant_width as u64 / width as u64).max(1) as u32;
+                            image.resize_exact(
+                                variant_width,
+                                variant_height,
+                                FilterType::CatmullRom,
+                            )
+                        }
+                    };
+                    let mut buf = Cursor::new(Vec::new());
+                    resized.write_to(&mut buf, format)?;
+                    std::fs::write(
+                        output_dir.join(variant.file_name("image")),
+                        buf.into_inner(),
+                    )?;
+                }
+                variants.push(variant);
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            variants,
+        })
+    }
+
+    /// Rewrites `<img>` to carry its intrinsic size, `srcset` and lazy loading, and wraps it in a
+    /// `<picture>` offering the additional formats.
+    fn rewrite(&self, tag: &str, src: &str) -> String {
+        let (dir, file_name) = match src.rsplit_once('/') {
+            Some((dir, file_name)) => (format!("{dir}/"), file_name),
+            None => (String::new(), src),
+        };
+        let file_name = urlencoding::decode(file_name)
+            .map(|item| item.into_owned())
+            .unwrap_or(file_name.into());
+        let srcset = |mime: &str| {
+            self.variants
+                .iter()
+                .filter(|item| item.mime == mime)
+                .map(|item| {
+                    let url = urlencoding::encode(&item.file_name(&file_name)).into_owned();
+                    format!("{dir}{url} {}w", item.width)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let sizes = format!("(max-width: {0}px) 100vw, {0}px", self.width);
+
+        let mut attrs = String::new();
+        let has_attr = |name: &str| {
+            regex!(r#"\s([\w-]+)="#)
+                .captures_iter(tag)
+                .any(|caps| &caps[1] == name)
+        };
+        let fallback = &self.variants[0].mime;
+        if !has_attr("srcset") {
+            attrs.push_str(&format!(
+                r#" srcset="{}" sizes="{sizes}""#,
+                srcset(fallback)
+            ));
+        }
+        if !has_attr("width") && !has_attr("height") {
+            attrs.push_str(&format!(
+                r#" width="{}" height="{}""#,
+                self.width, self.height
+            ));
+        }
+        if !has_attr("loading") {
+            attrs.push_str(r#" loading="lazy""#);
+        }
+        let end = tag
+            .strip_suffix("/>")
+            .or(tag.strip_suffix('>'))
+            .unwrap_or(tag);
+        let img = format!("{}{attrs}>", end.trim_end());
+
+        let mut mimes: Vec<&str> = Vec::new();
+        for variant in &self.variants {
+            if variant.mime != *fallback && !mimes.contains(&variant.mime.as_str()) {
+                mimes.push(&variant.mime);
+            }
+        }
+        if mimes.is_empty() {
+            return img;
+        }
+        let mut res = String::from("<picture>");
+        for mime in mimes {
+            res.push_str(&format!(
+                r#"<source type="{mime}" srcset="{}" sizes="{sizes}">"#,
+                srcset(mime)
+            ));
+        }
+        res.push_str(&img);
+        res.push_str("</picture>");
+        res
+    }
+}
+
+/// Generates responsive variants of the raster images of pages. Variants are cached in
+/// `cache_dir`, keyed by a hash of the source image and the settings, and copied next to the
+/// image in the output directory.
+#[derive(Debug)]
+pub struct ImageProcessor {
+    config: config::Images,
+    /// Only images inside of this directory are processed.
+    content_dir: PathBuf,
+    /// Variants are only written inside of this directory.
+    output_dir: PathBuf,
+    cache_dir: PathBuf,
+    /// Images processed so far, keyed by destination.
+    images: Mutex<HashMap<PathBuf, Arc<OnceCell<Option<ProcessedImage>>>>>,
+}
+
+impl ImageProcessor {
+    pub fn new(
+        config: &config::Images,
+        content_dir: impl AsRef<Path>,
+        output_dir: impl AsRef<Path>,
+        cache_dir: impl Into<PathBuf>,
+    ) -> Self {
+        for format in &config.formats {
+            if encoder(*format).is_none() {
+                warn!("Image format {format:?} is not supported by this build of asphinx.");
+            }
+        }
+        Self {
+            config: config.clone(),
+            content_dir: normalize(content_dir.as_ref()),
+            output_dir: normalize(output_dir.as_ref()),
+            cache_dir: cache_dir.into(),
+            images: Default::default(),
+        }
+    }
+
+    /// Processes the images at `urls`, as returned by `HtmlParser::get_image_urls`, and rewrites
+    /// the `<img>`s of `html` referring to them. Images which are not PNG, JPEG or WebP files
+    /// next to the page are left alone, as are those leading out of the content or output
+    /// directory.
+    pub async fn process_page(
+        &self,
+        html: &str,
+        urls: &[String],
+        source_dir: &Path,
+        dest_dir: &Path,
+    ) -> String {
+        if !self.config.enabled {
+            return html.into();
+        }
+
+        let mut processed = HashMap::new();
+        for url in urls {
+            let path = url.split(['?', '#']).next().unwrap_or_default();
+            let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+            if path.starts_with('/')
+                || !RASTER_EXTENSIONS.contains(&extension.as_deref().unwrap_or_default())
+            {
+                continue;
+            }
+            let source = normalize(&source_dir.join(path));
+            let dest = normalize(&dest_dir.join(path));
+            if !source.starts_with(&self.content_dir) || !dest.starts_with(&self.output_dir) {
+                warn!(
+                    "Ignore image {url}, it leads out of {} or {}",
+                    self.content_dir.display(),
+                    self.output_dir.display()
+                );
+                continue;
+            }
+            if let Some(image) = self.process(source, dest).await {
+                processed.insert(url.clone(), image);
+            }
+        }
+        if processed.is_empty() {
+            return html.into();
+        }
+
+        regex!(r#"<img\b[^>]*>"#)
+            .replace_all(html, |caps: &lazy_regex::Captures| {
+                let tag = &caps[0];
+                let src = regex!(r#"\ssrc="([^"]*)""#)
+                    .captures(tag)
+                    .map(|caps| caps[1].to_string());
+                let image = src.as_ref().and_then(|src| {
+                    let url = urlencoding::decode(src).ok()?;
+                    processed.get(url.as_ref())
+                });
+                match (src, image) {
+                    (Some(src), Some(image)) => image.rewrite(tag, &src),
+                    _ => tag.to_string(),
+                }
+            })
+            .into_owned()
+    }
+
+    /// Processes every image once, however many pages refer to it.
+    async fn process(&self, source: PathBuf, dest: PathBuf) -> Option<ProcessedImage> {
+        let cell = self
+            .images
+            .lock()
+            .unwrap()
+            .entry(dest.clone())
+            .or_default()
+            .clone();
+        cell.get_or_init(|| async {
+            let config = self.config.clone();
+            let cache_dir = self.cache_dir.clone();
+            let task = tokio::task::spawn_blocking(move || {
+                Self::process_blocking(&source, &dest, &config, &cache_dir)
+                    .map_err(|err| (source, err))
+            });
+            match task.await {
+                Ok(Ok(image)) => Some(image),
+                Ok(Err((source, err))) => {
+                    warn!("Process image {} failed: {err}", source.display());
+                    None
+                }
+                Err(err) => {
+                    error!("{err}");
+                    None
+                }
+            }
+        })
+        .await
+        .clone()
+    }
+
+    fn process_blocking(
+        source: &Path,
+        dest: &Path,
+        config: &config::Images,
+        cache_dir: &Path,
+    ) -> anyhow::Result<ProcessedImage> {
+        let content = std::fs::read(source)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&content);
+        hasher.update(serde_json::to_vec(&(
+            CACHE_VERSION,
+            &config.widths,
+            &config.formats,
+        ))?);
+        let cached = cache_dir.join(format!("{:x}", hasher.finalize()));
+
+        let meta_path = cached.join("meta.json");
+        let image = match std::fs::read(&meta_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<ProcessedImage>(&data).ok())
+        {
+            Some(image) => image,
+            None => {
+                debug!("Process image: {}", source.display());
+                let image = ProcessedImage::generate(&content, config, &cached)?;
+                std::fs::write(&meta_path, serde_json::to_vec(&image)?)?;
+                image
+            }
+        };
+
+        let file_name = dest
+            .file_name()
+            .map(|item| item.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let dest_dir = dest.parent().unwrap_or(Path::new(""));
+        std::fs::create_dir_all(dest_dir)?;
+        for variant in image.variants.iter().filter(|item| !item.original) {
+            std::fs::copy(
+                cached.join(variant.file_name("image")),
+                dest_dir.join(variant.file_name(&file_name)),
+            )?;
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    use super::ImageProcessor;
+    use crate::config;
+
+    #[tokio::test]
+    async fn test_process_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let content = root.join("content/a");
+        let public = root.join("public/a");
+        std::fs::create_dir_all(content.join("img")).unwrap();
+        DynamicImage::ImageRgb8(RgbImage::new(1000, 500))
+            .save_with_format(content.join("img/my shot.png"), ImageFormat::Png)
+            .unwrap();
+
+        let config = config::Images {
+            enabled: true,
+            widths: vec![480, 960, 2000],
+            ..Default::default()
+        };
+        let processor = ImageProcessor::new(
+            &config,
+            root.join("content"),
+            root.join("public"),
+            root.join("cache"),
+        );
+        let html = r#"<div class="content"><img src="img/my%20shot.png" alt="Shot"></div><img src="https://example.com/a.png"><img src="diagram.svg">"#;
+        let urls = vec!["img/my shot.png".to_string(), "diagram.svg".to_string()];
+        let res = processor.process_page(html, &urls, &content, &public).await;
+
+        let sizes = "(max-width: 1000px) 100vw, 1000px";
+        assert_eq!(
+            res,
+            format!(
+                r#"<div class="content"><picture><source type="image/webp" srcset="img/my%20shot-480w.webp 480w, img/my%20shot-960w.webp 960w, img/my%20shot-1000w.webp 1000w" sizes="{sizes}"><img src="img/my%20shot.png" alt="Shot" srcset="img/my%20shot-480w.png 480w, img/my%20shot-960w.png 960w, img/my%20shot.png 1000w" sizes="{sizes}" width="1000" height="500" loading="lazy"></picture></div><img src="https://example.com/a.png"><img src="diagram.svg">"#
+            )
+        );
+        let resized = image::open(public.join("img/my shot-480w.webp")).unwrap();
+        assert_eq!((resized.width(), resized.height()), (480, 240));
+        assert!(public.join("img/my shot-960w.png").exists());
+        assert_eq!(std::fs::read_dir(root.join("cache")).unwrap().count(), 1);
+
+        // Cached variants are copied without decoding the image again.
+        std::fs::remove_dir_all(&public).unwrap();
+        let processor = ImageProcessor::new(
+            &config,
+            root.join("content"),
+            root.join("public"),
+            root.join("cache"),
+        );
+        let again = processor.process_page(html, &urls, &content, &public).await;
+        assert_eq!(again, res);
+        assert!(public.join("img/my shot-1000w.webp").exists());
+
+        // Photos only get resized JPEGs.
+        DynamicImage::ImageRgb8(RgbImage::new(600, 300))
+            .save_with_format(content.join("img/photo.jpg"), ImageFormat::Jpeg)
+            .unwrap();
+        let html = r#"<img src="img/photo.jpg">"#;
+        let urls = vec!["img/photo.jpg".to_string()];
+        let res = processor.process_page(html, &urls, &content, &public).await;
+        assert!(!res.contains("<picture>"), "{res}");
+        assert!(res.contains("img/photo-480w.jpg 480w"), "{res}");
+        assert!(!public.join("img/photo-480w.webp").exists());
+
+        // Images out of the content directory are left alone.
+        DynamicImage::ImageRgb8(RgbImage::new(600, 300))
+            .save_with_format(root.join("secret.png"), ImageFormat::Png)
+            .unwrap();
+        let html = r#"<img src="../../secret.png">"#;
+        let urls = vec!["../../secret.png".to_string()];
+        let res = processor.process_page(html, &urls, &content, &public).await;
+        assert_eq!(res, html);
+        assert!(!root.join("secret-480w.png").exists());
+    }
+}
@@ -12,8 +12,14 @@ static DB: LazyLock<redb::Database> = LazyLock::new(|| {
         .unwrap()
 });
 
-// (Content, Title)
-pub type CacheValue = (String, String, Option<String>);
+// (Anchor, Heading, Breadcrumb, Text)
+pub type SectionValue = (Option<String>, String, Vec<String>, String);
+
+// (Summary, Word count, Reading time)
+pub type SummaryValue = (Option<String>, u64, u64);
+
+// (Title, Last modified, Summary, Sections)
+pub type CacheValue = (String, Option<String>, SummaryValue, Vec<SectionValue>);
 
 fn table_definition() -> redb::TableDefinition<'static, std::string::String, CacheValue> {
     TableDefinition::new(TABLE_NAME)
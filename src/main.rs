@@ -1,74 +1,88 @@
 #![allow(dead_code)]
 
+mod assets;
+mod book;
+mod codeblock;
 mod config;
+mod diagrams;
+mod epub;
 use console::Emoji;
-use fs_more::{
-    directory::{
-        copy_directory_with_progress, CollidingSubDirectoryBehaviour, DestinationDirectoryRule,
-        DirectoryCopyWithProgressOptions,
-    },
-    file::CollidingFileBehaviour,
-};
 use futures::{stream, StreamExt};
 use index::index_clear;
 pub mod error;
 mod filetree;
 mod generator;
+mod highlight;
+mod images;
 mod index;
+mod markdown;
+mod math;
+mod plugin;
+mod search;
+mod site;
+mod sitemap;
+mod source;
+mod summary;
+mod theme;
+mod url;
 mod utils;
 use crate::index::index_list;
-use std::path;
-use tokio::fs;
+use std::{path::Path, sync::Arc};
 use tokio::time::Instant;
 use utils::cpu_num;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
-use lazy_regex::regex;
 use tracing::*;
 
 use crate::{
-    config::Config, filetree::generate_filetree_json, generator::AdocGenerator, utils::GitInfo,
+    assets::AssetManifest,
+    config::Config,
+    filetree::{generate_filetree_json, FileTreeData},
+    generator::AdocGenerator,
+    search::{generate_pagefind, SearchIndex},
+    site::{IndexNode, Page, Site},
+    sitemap::generate_sitemap,
+    summary::Summarizer,
+    theme::Theme,
+    url::UrlBuilder,
+    utils::GitInfo,
 };
-use pagefind::runner::run_indexer;
-
-fn parse_index_file(file_path_str: String) -> Vec<String> {
-    let mut result = Vec::<String>::new();
-    debug!("process file: {}", file_path_str);
-
-    let file_path = path::Path::new(&file_path_str);
-    if !file_path.exists() {
-        warn!("file doesn't existes: {}", file_path.display());
-        return result;
-    }
-
-    if file_path.ends_with("index.adoc") {
-        let dir_path = file_path.parent().unwrap();
-        let content = std::fs::read_to_string(&file_path_str).unwrap();
-
-        let re = regex!(r"xref:(.*)\[.*\]");
-        for item in re.captures_iter(&content) {
-            let file_name: String = item.get(1).unwrap().as_str().replace("{cpp}", "c++");
-            let file_path: String = dir_path.join(file_name.as_str()).to_str().unwrap().into();
-            result.append(&mut parse_index_file(file_path));
-        }
-    }
-
-    result.push(file_path_str);
-
-    result
-}
 
 #[derive(Debug, Parser)]
 struct Args {
     #[arg(long, default_value_t = false)]
     minify: bool,
-    #[arg(long, default_value = "")]
-    theme: String,
+    /// Path to the theme directory, the embedded theme is used when omitted
+    #[arg(long)]
+    theme: Option<String>,
     #[arg(short, long, default_value = "./asphinx.toml")]
     config: String,
-    #[arg(long, default_value = None)]
-    site: Option<String>,
+    /// Skip building the search indexes
+    #[arg(long, default_value_t = false)]
+    no_search: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Export a section of the site instead of building it
+    #[command(subcommand)]
+    Export(Export),
+}
+
+#[derive(Debug, Subcommand)]
+enum Export {
+    /// Write the pages of a section, e.g. `content/book1`, as one printable `book.html`
+    Book {
+        section: String,
+        /// Also write `book.pdf` with asciidoctor-pdf
+        #[arg(long, default_value_t = false)]
+        pdf: bool,
+    },
+    /// Write the pages of a section as an EPUB 3 `book.epub`
+    Epub { section: String },
 }
 
 static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
@@ -77,17 +91,6 @@ static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
 async fn main() {
     let _ = index_clear();
     let args = Args::parse();
-    if args.site.is_some() {
-        println!("xxx: {:?}", args.site);
-        match run_indexer().await {
-            Ok(_) => {}
-            Err(msg) => {
-                eprintln!("{msg}");
-            }
-        }
-        return;
-    }
-    assert!(!args.theme.is_empty(), "theme can not be empty");
     init_logger();
 
     let started = Instant::now();
@@ -96,13 +99,79 @@ async fn main() {
 
     let pb = mpb.add(ProgressBar::new_spinner());
 
-    let gitinfo = GitInfo::new(".".to_string(), pb).await.unwrap();
-
     let entry_file = "content/index.adoc";
 
     let config = Config::from_path(args.config).await;
     debug!(?config);
-    let generator = AdocGenerator::new(args.theme.clone(), config.clone());
+    let theme = match Theme::load(args.theme.as_deref(), &config.theme) {
+        Ok(theme) => theme,
+        Err(err) => {
+            error!("Load theme failed: {err}");
+            std::process::exit(1);
+        }
+    };
+    let urls = UrlBuilder::from_config(&config);
+
+    if let Some(Command::Export(export)) = args.command {
+        pb.finish_and_clear();
+        let generator = AdocGenerator::new(&theme, config.clone(), urls, Default::default());
+        let res = match export {
+            Export::Book { section, pdf } => book::export(&generator, &section, pdf).await,
+            Export::Epub { section } => epub::export(&generator, &section)
+                .await
+                .map(|file| vec![file]),
+        };
+        match res {
+            Ok(files) => {
+                for file in files {
+                    info!("Wrote {}", file.display());
+                }
+                println!("{} Done in {}", SPARKLE, HumanDuration(started.elapsed()));
+            }
+            Err(err) => {
+                error!("Export failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let gitinfo = GitInfo::new(".".to_string(), pb).await.unwrap();
+
+    // Pages link to the published names of the assets, so they are written first.
+    let manifest = {
+        let pb = mpb.add(ProgressBar::new_spinner());
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                .unwrap(),
+        );
+        pb.set_message("Writing assets...");
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+        let mut assets = match theme.assets() {
+            Ok(assets) => assets,
+            Err(err) => {
+                error!("Read theme assets failed: {err}");
+                Default::default()
+            }
+        };
+        if config.build_highlighting() {
+            match highlight::theme_css(&config.highlight) {
+                Ok(css) => {
+                    assets.insert("highlight.css".into(), css.into_bytes());
+                }
+                Err(err) => error!("Generate highlight.css failed: {err}"),
+            }
+        }
+        let (manifest, files) = AssetManifest::build(assets, config.assets.fingerprint);
+        match manifest.write(&files, Path::new("public")).await {
+            Ok(_) => pb.finish_with_message(format!("Wrote {} assets.", manifest.len())),
+            Err(err) => pb.finish_with_message(format!("Write assets failed: {err}")),
+        }
+        Arc::new(manifest)
+    };
+    let generator = AdocGenerator::new(&theme, config.clone(), urls.clone(), manifest);
 
     let pb = mpb.add(ProgressBar::new_spinner());
     pb.set_style(
@@ -113,7 +182,11 @@ async fn main() {
     pb.set_message("Parse Index info...");
     pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-    let files = parse_index_file(entry_file.into());
+    let index_tree = IndexNode::parse(entry_file);
+    let files = index_tree
+        .as_ref()
+        .map(IndexNode::files)
+        .unwrap_or_default();
 
     pb.finish_with_message(format!(
         "Parse index completed, total {} file.",
@@ -146,7 +219,15 @@ async fn main() {
         raw_html
     };
 
-    {
+    let summarizer = Summarizer::new(&config);
+    let mut pages = Vec::with_capacity(raw_html.len());
+    for (ctx, html) in &raw_html {
+        pages.push(Page::new(ctx, html, &urls, &gitinfo, &summarizer).await);
+    }
+    let site = Site::new(index_tree.as_ref(), pages, urls.clone());
+    let tree = FileTreeData::generate("content", &site);
+
+    let failed_pages = {
         let total_files = raw_html.len();
         let pb = mpb.add(ProgressBar::new(total_files as u64));
         pb.set_style(ProgressStyle::default_bar()
@@ -155,83 +236,69 @@ async fn main() {
         .progress_chars("━━╾╴─"));
         pb.set_message("Render HTML ...");
 
-        let _: Vec<_> = stream::iter(raw_html.into_iter().map(|(ctx, html)| async {
+        let failures: Vec<_> = stream::iter(raw_html.into_iter().map(|(ctx, html)| async {
             let pb_clone = pb.clone();
             {
                 pb_clone.set_message(format!("render {} ...", ctx.source_file));
                 let result = generator
-                    .render_html(ctx, html, &gitinfo, args.minify)
+                    .render_html(ctx, html, &site, &tree, args.minify)
                     .await;
                 pb_clone.inc(1);
                 result
             }
         }))
         .buffer_unordered(cpu_num())
+        .filter_map(|v| async { v.err() })
         .collect()
         .await;
-        pb.finish_with_message("Render all files");
-    }
+        for err in &failures {
+            error!("{err}");
+        }
+        if failures.is_empty() {
+            pb.finish_with_message("Render all files");
+        } else {
+            pb.finish_with_message(format!("Render failed for {} files", failures.len()));
+        }
+        failures.len()
+    };
 
-    let asset_path = path::Path::new(&args.theme).join("assets");
-    if asset_path.is_dir() {
+    {
+        let resources = generator.resources();
         let pb = mpb.add(ProgressBar::new_spinner());
         pb.set_style(
             ProgressStyle::default_spinner()
                 .template("{spinner:.green} [{elapsed_precise}] {msg}")
                 .unwrap(),
         );
-        pb.set_message("Copying assets...");
+        pb.set_message("Copying resources...");
         pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        let pwd = std::env::current_dir().unwrap();
-        let pwd = pwd.join("public/assets");
-
-        let _ = tokio::task::spawn_blocking(move || {
-            let ret = copy_directory_with_progress(
-                asset_path,
-                pwd,
-                DirectoryCopyWithProgressOptions {
-                    destination_directory_rule: DestinationDirectoryRule::AllowNonEmpty {
-                        colliding_file_behaviour: CollidingFileBehaviour::Overwrite,
-                        colliding_subdirectory_behaviour: CollidingSubDirectoryBehaviour::Continue,
-                    },
-                    ..Default::default()
-                },
-                |process| {
-                    pb.set_message(format!(
-                        "{}/{}",
-                        process.current_operation_index, process.total_operations,
-                    ));
-                },
+        let report = resources.copy().await;
+        for item in &report.missing {
+            warn!(
+                "Missing resource {} referenced from {}",
+                item.source.display(),
+                item.page
             );
-            pb.finish_with_message("Copy assets completed.");
-            if let Err(e) = ret {
-                error!(%e);
-            }
-        })
-        .await;
-    }
-
-    let pb = mpb.add(ProgressBar::new_spinner());
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} [{elapsed_precise}] {msg}")
-            .unwrap(),
-    );
-    pb.set_message("Generating index file...");
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
-
-    match index_list() {
-        Ok(index) => {
-            if let Ok(index) = serde_json::to_string(&index) {
-                debug!(%index);
-                let _ = fs::write("public/cache.json", &index).await;
-                pb.finish_with_message("Generated index file.");
-            }
         }
-        Err(err) => {
-            pb.finish_with_message(format!("Generated index file failed: {err}"));
+        for (item, err) in &report.failed {
+            error!(
+                "Copy {} -> {} failed: {err}",
+                item.source.display(),
+                item.dest.display()
+            );
         }
+        pb.finish_with_message(format!(
+            "Copied {} resources, {} missing, {} failed.",
+            report.copied,
+            report.missing.len(),
+            report.failed.len()
+        ));
+    }
+
+    let removed = generator.diagrams().clean();
+    if removed > 0 {
+        info!("Removed {removed} stale diagram files.");
     }
 
     let pb = mpb.add(ProgressBar::new_spinner());
@@ -243,7 +310,7 @@ async fn main() {
     pb.set_message("Generating file tree...");
     pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-    match generate_filetree_json("content", "public/filetree.json").await {
+    match generate_filetree_json(&tree, "public", &config.filetree).await {
         Ok(_) => {
             pb.finish_with_message("Generated file tree.");
         }
@@ -251,16 +318,61 @@ async fn main() {
             pb.finish_with_message(format!("Generated file tree failed: {err}"));
         }
     }
-    let cmd = std::env::args_os().next();
-    if let Some(cmd) = cmd {
-        let mut child = tokio::process::Command::new(cmd)
-            .arg("--site")
-            .arg("public")
-            .spawn()
-            .expect("Failed to restart server");
-        let _ = child.wait().await;
+    match generate_sitemap(&site, "public/sitemap.xml").await {
+        Ok(true) => info!("Generated sitemap."),
+        Ok(false) => {}
+        Err(err) => error!("Generated sitemap failed: {err}"),
     }
 
+    if !args.no_search {
+        let pb = mpb.add(ProgressBar::new_spinner());
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                .unwrap(),
+        );
+        pb.set_message("Generating search index...");
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+        let search_index = index_list().map(SearchIndex::from_cache);
+        match search_index {
+            Ok(index) => match index.write("public/search").await {
+                Ok(_) => pb.finish_with_message("Generated search index."),
+                Err(err) => pb.finish_with_message(format!("Generated search index failed: {err}")),
+            },
+            Err(err) => {
+                pb.finish_with_message(format!("Generated search index failed: {err}"));
+            }
+        }
+
+        let pb = mpb.add(ProgressBar::new_spinner());
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                .unwrap(),
+        );
+        pb.set_message("Running pagefind...");
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+        match generate_pagefind("public", &config.search).await {
+            Ok(pages) => pb.finish_with_message(format!("Indexed {pages} pages with pagefind.")),
+            Err(err) => pb.finish_with_message(format!("Run pagefind failed: {err}")),
+        }
+    }
+
+    generator
+        .plugins()
+        .site_finished(Path::new("public"))
+        .await;
+
+    if failed_pages > 0 {
+        eprintln!(
+            "{} pages failed to render in {}",
+            failed_pages,
+            HumanDuration(started.elapsed())
+        );
+        std::process::exit(1);
+    }
     println!("{} Done in {}", SPARKLE, HumanDuration(started.elapsed()));
 }
 
@@ -0,0 +1,521 @@
+use std::{collections::HashMap, path::Path};
+
+use lazy_regex::regex;
+use pulldown_cmark::{html, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+use crate::{site::normalize, utils::escape};
+
+/// Splits the front matter off a Markdown document: TOML between `+++` lines or YAML between
+/// `---` lines. Values are flattened to strings, lists being joined with commas as for the
+/// `:tags:` attribute of AsciiDoc pages.
+pub fn front_matter(content: &str) -> (HashMap<String, String>, &str) {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let Some(delimiter) = ["+++", "---"]
+        .into_iter()
+        .find(|delimiter| content.lines().next().map(str::trim_end) == Some(*delimiter))
+    else {
+        return (HashMap::new(), content);
+    };
+    let start = content
+        .find('\n')
+        .map(|idx| idx + 1)
+        .unwrap_or(content.len());
+    let mut end = start;
+    let mut body = None;
+    for line in content[start..].split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            body = Some(&content[end + line.len()..]);
+            break;
+        }
+        end += line.len();
+    }
+    let Some(body) = body else {
+        return (HashMap::new(), content);
+    };
+    let header = &content[start..end];
+    let attributes = match delimiter {
+        "+++" => toml_attributes(header),
+        _ => yaml_attributes(header),
+    };
+    (attributes, body)
+}
+
+fn toml_attributes(header: &str) -> HashMap<String, String> {
+    let table: toml::Table = match toml::from_str(header) {
+        Ok(table) => table,
+        Err(err) => {
+            tracing::warn!("Invalid front matter: {err}");
+            return HashMap::new();
+        }
+    };
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        toml::Value::String(item) => item,
+                        item => item.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                value => value.to_string(),
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+/// The flat subset of YAML front matter uses: `key: value`, `key: [a, b]` and `key:` followed
+/// by `- item` lines.
+fn yaml_attributes(header: &str) -> HashMap<String, String> {
+    let unquote = |value: &str| {
+        let value = value.trim();
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .or_else(|| {
+                value
+                    .strip_prefix('\'')
+                    .and_then(|value| value.strip_suffix('\''))
+            })
+            .unwrap_or(value)
+            .to_string()
+    };
+    let mut res: HashMap<String, String> = HashMap::new();
+    let mut list: Option<(String, Vec<String>)> = None;
+    for line in header.lines() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if let (Some((_, items)), Some(item)) = (&mut list, line.trim().strip_prefix("- ")) {
+            items.push(unquote(item));
+            continue;
+        }
+        if let Some((key, items)) = list.take() {
+            res.insert(key, items.join(", "));
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        let value = value.trim();
+        if value.is_empty() {
+            list = Some((key, Vec::new()));
+        } else if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let items: Vec<_> = items
+                .split(',')
+                .map(unquote)
+                .filter(|i| !i.is_empty())
+                .collect();
+            res.insert(key, items.join(", "));
+        } else {
+            res.insert(key, unquote(value));
+        }
+    }
+    if let Some((key, items)) = list {
+        res.insert(key, items.join(", "));
+    }
+    res
+}
+
+/// Anchor of a heading the way asciidoctor builds them: `_` followed by the lowercase words of
+/// the heading joined by `_`.
+fn heading_id(text: &str) -> String {
+    let text = text.to_lowercase();
+    let id = regex!(r"[^\w]+").replace_all(&text, "_");
+    format!("_{}", id.trim_matches('_'))
+}
+
+/// Renders a Markdown page to a document shaped like the output of asciidoctor, so that it goes
+/// through the same processing: the title comes from the front matter or the leading `#`
+/// heading, headings open `sect1`, `sect2`… blocks with a table of contents, and fenced code
+/// blocks become listing blocks.
+pub fn render(content: &str) -> String {
+    let (attributes, body) = front_matter(content);
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+    let mut events = Parser::new_ext(body, options).peekable();
+
+    let mut title = attributes.get("title").cloned().map(|title| escape(&title));
+    if let Some(Event::Start(Tag::Heading {
+        level: HeadingLevel::H1,
+        ..
+    })) = events.peek()
+    {
+        events.next();
+        let mut inner = Vec::new();
+        for event in events.by_ref() {
+            if let Event::End(TagEnd::Heading(_)) = event {
+                break;
+            }
+            inner.push(event);
+        }
+        if title.is_none() {
+            let mut heading = String::new();
+            html::push_html(&mut heading, inner.into_iter());
+            title = Some(heading);
+        }
+    }
+
+    let mut out = Vec::new();
+    // Levels of the sections still open.
+    let mut sections: Vec<usize> = Vec::new();
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut toc: Vec<(usize, String, String)> = Vec::new();
+    let mut code: Option<(Option<String>, String)> = None;
+    let close = |sections: &mut Vec<usize>, level: usize, out: &mut Vec<Event>| {
+        while sections.last().is_some_and(|open| *open >= level) {
+            let html = match sections.pop() {
+                Some(1) => "</div>\n</div>\n",
+                _ => "</div>\n",
+            };
+            out.push(Event::Html(html.into()));
+        }
+    };
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                let mut inner = Vec::new();
+                let mut text = String::new();
+                for event in events.by_ref() {
+                    match &event {
+                        Event::End(TagEnd::Heading(_)) => break,
+                        Event::Text(value) | Event::Code(value) => text.push_str(value),
+                        _ => {}
+                    }
+                    inner.push(event);
+                }
+                let mut heading = String::new();
+                html::push_html(&mut heading, inner.into_iter());
+
+                let mut id = id
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| heading_id(&text));
+                let count = ids.entry(id.clone()).or_default();
+                *count += 1;
+                if *count > 1 {
+                    id = format!("{id}_{count}");
+                }
+
+                // Asciidoctor articles have no level 0 sections, later H1s become level 1.
+                let level = (level as usize - 1).max(1);
+                close(&mut sections, level, &mut out);
+                sections.push(level);
+                let tag = level + 1;
+                let mut html = format!(
+                    "<div class=\"sect{level}\">\n<h{tag} id=\"{id}\">{heading}</h{tag}>\n"
+                );
+                if level == 1 {
+                    html.push_str("<div class=\"sectionbody\">\n");
+                }
+                out.push(Event::Html(html.into()));
+                toc.push((level, id, heading));
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split([' ', ',', '{'])
+                        .next()
+                        .filter(|lang| !lang.is_empty())
+                        .map(String::from),
+                    CodeBlockKind::Indented => None,
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                code.as_mut().unwrap().1.push_str(&text);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let (lang, text) = code.take().unwrap_or_default();
+                let text = escape(text.strip_suffix('\n').unwrap_or(&text));
+                let html = match lang {
+                    Some(lang) => format!(
+                        "<div class=\"listingblock\">\n<div class=\"content\">\n<pre class=\"highlight\"><code class=\"language-{lang}\" data-lang=\"{lang}\">{text}</code></pre>\n</div>\n</div>\n",
+                        lang = escape(&lang)
+                    ),
+                    None => format!(
+                        "<div class=\"listingblock\">\n<div class=\"content\">\n<pre>{text}</pre>\n</div>\n</div>\n"
+                    ),
+                };
+                out.push(Event::Html(html.into()));
+            }
+            event => out.push(event),
+        }
+    }
+    close(&mut sections, 0, &mut out);
+
+    let mut content = String::new();
+    html::push_html(&mut content, out.into_iter());
+    let title = title.unwrap_or_else(|| "Untitled".into());
+    let plain_title = regex!(r"<[^>]*>").replace_all(&title, "");
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"UTF-8\">\n<title>{plain_title}</title>\n</head>\n<body class=\"article\">\n<div id=\"header\">\n<h1>{title}</h1>\n{}</div>\n<div id=\"content\">\n{content}</div>\n</body>\n</html>\n",
+        escape(attributes.get("lang").map(String::as_str).unwrap_or("en")),
+        render_toc(&toc),
+    )
+}
+
+/// Table of contents of the `sect1` and `sect2` headings, as asciidoctor writes it.
+fn render_toc(toc: &[(usize, String, String)]) -> String {
+    let mut items: Vec<(String, Vec<String>)> = Vec::new();
+    for (level, id, heading) in toc {
+        let link = format!("<a href=\"#{id}\">{heading}</a>");
+        match (level, items.last_mut()) {
+            (1, _) | (2, None) => items.push((link, Vec::new())),
+            (2, Some((_, children))) => children.push(link),
+            _ => {}
+        }
+    }
+    if items.is_empty() {
+        return String::new();
+    }
+    let mut res = String::from(
+        "<div id=\"toc\" class=\"toc\">\n<div id=\"toctitle\">Table of Contents</div>\n<ul class=\"sectlevel1\">\n",
+    );
+    for (link, children) in items {
+        res.push_str(&format!("<li>{link}"));
+        if !children.is_empty() {
+            res.push_str("\n<ul class=\"sectlevel2\">\n");
+            for child in children {
+                res.push_str(&format!("<li>{child}</li>\n"));
+            }
+            res.push_str("</ul>\n");
+        }
+        res.push_str("</li>\n");
+    }
+    res.push_str("</ul>\n</div>\n");
+    res
+}
+
+/// Points relative links to Markdown pages, from xrefs of AsciiDoc pages or links of Markdown
+/// pages, at the HTML pages built from them. Only links from a page in `source_dir` to a source
+/// file for which `is_page` holds are rewritten, so links to other Markdown files are kept.
+pub fn rewrite_links(html: &str, source_dir: &Path, is_page: impl Fn(&str) -> bool) -> String {
+    regex!(r#"(\shref=")([^":?#]+)\.(md|markdown)((?:#[^"]*)?")"#)
+        .replace_all(html, |caps: &lazy_regex::Captures| {
+            let file = format!("{}.{}", &caps[2], &caps[3]);
+            let file = urlencoding::decode(&file).map_or(file.clone(), |file| file.into_owned());
+            let target = normalize(&source_dir.join(file));
+            match is_page(&target.to_string_lossy()) {
+                true => format!("{}{}.html{}", &caps[1], &caps[2], &caps[4]),
+                false => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{front_matter, render, rewrite_links};
+    use crate::{
+        config,
+        filetree::FileTreeData,
+        generator::AdocGenerator,
+        site::{IndexNode, Page, Site},
+        theme::Theme,
+        utils::HtmlParser,
+    };
+
+    #[test]
+    fn test_front_matter() {
+        let (attributes, body) =
+            front_matter("+++\ntitle = \"Intro\"\ntags = [\"a\", \"b\"]\n+++\n# Body\n");
+        assert_eq!(attributes["title"], "Intro");
+        assert_eq!(attributes["tags"], "a, b");
+        assert_eq!(body, "# Body\n");
+
+        let (attributes, body) = front_matter(
+            "---\ntitle: 'Intro: part 1'\ntags:\n  - a\n  - \"b\"\ndescription: Short\nkeywords: [x, y]\n---\nBody\n",
+        );
+        assert_eq!(attributes["title"], "Intro: part 1");
+        assert_eq!(attributes["tags"], "a, b");
+        assert_eq!(attributes["description"], "Short");
+        assert_eq!(attributes["keywords"], "x, y");
+        assert_eq!(body, "Body\n");
+
+        let (attributes, body) = front_matter("---\nno end\n");
+        assert!(attributes.is_empty());
+        assert_eq!(body, "---\nno end\n");
+    }
+
+    #[test]
+    fn test_render() {
+        let html = render(
+            "# Guide & more\n\nIntro.\n\n## Setup\n\n```rust\nfn main() {}\n```\n\n### Details {#details}\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n## Setup\n\nAgain, see [next](next.md#top).\n",
+        );
+        let parser = HtmlParser::new(&html);
+        assert_eq!(parser.get_title(), "Guide &amp; more");
+        let content = parser.get_content().unwrap();
+        assert!(content.starts_with("<p>Intro.</p>"), "{content}");
+        assert!(
+            content.contains(
+                r#"<div class="sect1">
+<h2 id="_setup">Setup</h2>
+<div class="sectionbody">"#
+            ),
+            "{content}"
+        );
+        assert!(
+            content.contains(r#"<pre class="highlight"><code class="language-rust" data-lang="rust">fn main() {}</code></pre>"#),
+            "{content}"
+        );
+        assert!(
+            content.contains(r#"<h3 id="details">Details</h3>"#),
+            "{content}"
+        );
+        assert!(content.contains("<table>"), "{content}");
+        assert!(
+            content.contains(r#"<h2 id="_setup_2">Setup</h2>"#),
+            "{content}"
+        );
+        assert!(
+            parser
+                .get_toc()
+                .unwrap()
+                .contains(r##"<a href="#details">Details</a>"##),
+            "{html}"
+        );
+        let sections = parser.sections(&Default::default());
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[2].id.as_deref(), Some("details"));
+        assert_eq!(sections[2].breadcrumb, vec!["Setup".to_string()]);
+
+        let html = render("---\ntitle: From front matter\n---\n# Ignored\n\nText\n");
+        assert_eq!(HtmlParser::new(&html).get_title(), "From front matter");
+        assert!(!html.contains("Ignored"));
+    }
+
+    #[test]
+    fn test_render_h1_sections() {
+        let html = render("# Title\n\nIntro.\n\n# Second\n\nText.\n\n## Sub\n\nMore.\n");
+        assert!(!html.contains("sect0"), "{html}");
+        assert!(
+            html.contains(
+                r#"<div class="sect1">
+<h2 id="_second">Second</h2>"#
+            ),
+            "{html}"
+        );
+        let parser = HtmlParser::new(&html);
+        let sections = parser.sections(&Default::default());
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].id.as_deref(), Some("_second"));
+        assert_eq!(sections[2].id.as_deref(), Some("_sub"));
+        assert!(parser
+            .get_toc()
+            .unwrap()
+            .contains(r##"<a href="#_second">Second</a>"##));
+    }
+
+    #[tokio::test]
+    async fn test_index_xref_to_markdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let content = root.join("content");
+        std::fs::create_dir_all(&content).unwrap();
+        std::fs::write(
+            content.join("index.adoc"),
+            "= Home\n\n* xref:guide.md[Guide]\n* xref:other.md[Other]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            content.join("guide.md"),
+            "---\ntags: [a]\n---\n# Guide\n\nSee [the other page](other.md) and [the changes](CHANGELOG.md).\n",
+        )
+        .unwrap();
+        std::fs::write(content.join("other.md"), "# Other\n").unwrap();
+
+        let index_file = content.join("index.adoc");
+        let index = IndexNode::parse(index_file.to_str().unwrap()).unwrap();
+        let guide_file = content.join("guide.md").to_string_lossy().into_owned();
+        assert_eq!(index.files()[1], guide_file);
+
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let generator = AdocGenerator::new(
+            &theme,
+            config::Config::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let (ctx, html) = generator
+            .generate_html(guide_file.clone().into())
+            .await
+            .unwrap();
+        let page = |source_file: &str, dest_file: &str, path: &str, title: &str| Page {
+            source_file: source_file.into(),
+            dest_file: dest_file.into(),
+            path: path.into(),
+            title: title.into(),
+            attributes: Default::default(),
+            last_modified: None,
+            summary: Default::default(),
+        };
+        let site = Site::new(
+            Some(&index),
+            vec![
+                page(index_file.to_str().unwrap(), "", "index.html", "Home"),
+                page(
+                    &guide_file,
+                    &ctx.dest_file,
+                    "guide.html",
+                    &HtmlParser::new(&html).get_title(),
+                ),
+                page(
+                    content.join("other.md").to_str().unwrap(),
+                    "",
+                    "other.html",
+                    "Other",
+                ),
+            ],
+            Default::default(),
+        );
+        let tree = FileTreeData::generate(content.to_str().unwrap(), &site);
+        let dest_file = ctx.dest_file.clone();
+        generator
+            .render_html(ctx, html, &site, &tree, false)
+            .await
+            .unwrap();
+
+        let res = std::fs::read_to_string(&dest_file).unwrap();
+        assert!(dest_file.ends_with("public/guide.html"), "{dest_file}");
+        assert!(res.contains("<h1>Guide</h1>"), "{res}");
+        assert!(
+            res.contains(r#"<a href="other.html">the other page</a>"#),
+            "{res}"
+        );
+        assert!(
+            res.contains(r#"<a href="CHANGELOG.md">the changes</a>"#),
+            "{res}"
+        );
+        assert!(
+            res.contains(r#"<a href="/guide.html" aria-current="page">Guide</a>"#),
+            "{res}"
+        );
+        assert!(
+            res.contains(r#"<a class="prev" href="/index.html">← Home</a>"#),
+            "{res}"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let pages = ["content/a/guide/intro.md", "content/b.md"];
+        assert_eq!(
+            rewrite_links(
+                r#"<a href="guide/intro.md">a</a> <a href="../b.md#x">b</a> <a href="https://e.com/c.md">c</a> <a href="CHANGELOG.md">d</a>"#,
+                Path::new("content/a"),
+                |file| pages.contains(&file),
+            ),
+            r#"<a href="guide/intro.html">a</a> <a href="../b.html#x">b</a> <a href="https://e.com/c.md">c</a> <a href="CHANGELOG.md">d</a>"#
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use lazy_regex::regex;
+use tracing::*;
+
+use crate::utils::unescape;
+
+/// latex2mathml writes the `<`, `>` and `&` of operators, identifiers and text unescaped.
+fn escape_tokens(mathml: &str) -> String {
+    regex!(r"(?s)(<(?:mo|mi|mn|mtext)\b[^>]*>)(.*?)(</(?:mo|mi|mn|mtext)>)")
+        .replace_all(mathml, |caps: &lazy_regex::Captures| {
+            let text = caps[2]
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            format!("{}{text}{}", &caps[1], &caps[3])
+        })
+        .into_owned()
+}
+
+fn render_formulas(html: &str) -> String {
+    let re = regex!(r"(?s)\\\[(.+?)\\\]|\\\((.+?)\\\)");
+    re.replace_all(html, |caps: &lazy_regex::Captures| {
+        let (latex, display) = match caps.get(1) {
+            Some(latex) => (latex, DisplayStyle::Block),
+            None => (caps.get(2).unwrap(), DisplayStyle::Inline),
+        };
+        match latex_to_mathml(unescape(latex.as_str()).trim(), display) {
+            Ok(mathml) => escape_tokens(&mathml),
+            Err(err) => {
+                warn!("Failed to convert formula `{}`: {err}", latex.as_str());
+                caps[0].to_string()
+            }
+        }
+    })
+    .into_owned()
+}
+
+/// Whether `html` is a page of a document using `:stem:`, for which asciidoctor loads MathJax, or
+/// containing stem blocks.
+fn uses_stem(html: &str) -> bool {
+    regex!(r#"<script type="text/x-mathjax-config">|\sclass="stemblock[\s"]"#).is_match(html)
+}
+
+/// Converts the LaTeX math asciidoctor leaves in pages using `:stem: latexmath` to MathML:
+/// `\(...\)` inline and `\[...\]`, which stem blocks contain, as a block. Pages without stem,
+/// code, scripts and formulas that fail to convert are left as they are.
+pub fn render_mathml(html: &str) -> String {
+    if !uses_stem(html) {
+        return html.into();
+    }
+    let skip = regex!(r"(?s)<pre\b.*?</pre>|<code\b.*?</code>|<script\b.*?</script>");
+    let mut res = String::with_capacity(html.len());
+    let mut last = 0;
+    for item in skip.find_iter(html) {
+        res.push_str(&render_formulas(&html[last..item.start()]));
+        res.push_str(item.as_str());
+        last = item.end();
+    }
+    res.push_str(&render_formulas(&html[last..]));
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::render_mathml;
+
+    #[test]
+    fn test_render_mathml() {
+        let html = r#"<div class="stemblock"><div class="content">\[a &lt; b\]</div></div>
+<p>Let \(x^2\) be, \(\frac{1}{\)</p><pre>\(y\)</pre><code>\[z\]</code>"#;
+        let res = render_mathml(html);
+        assert!(
+            res.starts_with(r#"<div class="stemblock"><div class="content"><math xmlns="http://www.w3.org/1998/Math/MathML" display="block"><mi>a</mi><mo>&lt;</mo><mi>b</mi></math></div></div>"#),
+            "{res}"
+        );
+        assert!(res.contains(r#"<p>Let <math xmlns="http://www.w3.org/1998/Math/MathML" display="inline"><msup><mi>x</mi><mn>2</mn></msup></math> be, \(\frac{1}{\)</p>"#), "{res}");
+        assert!(res.ends_with(r#"<pre>\(y\)</pre><code>\[z\]</code>"#));
+    }
+
+    #[test]
+    fn test_render_mathml_stem() {
+        let html = r#"<p>Costs \(5\) to \(6\).</p>"#;
+        assert_eq!(render_mathml(html), html);
+
+        let html = r#"<p>\(x \& y\)</p><script type="text/x-mathjax-config">"#;
+        assert_eq!(
+            render_mathml(html),
+            r#"<p><math xmlns="http://www.w3.org/1998/Math/MathML" display="inline"><mi>x</mi><mi mathvariant="normal">&amp;</mi><mi>y</mi></math></p><script type="text/x-mathjax-config">"#
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    fmt,
+    io::{Read, Write},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tracing::*;
+
+use crate::{
+    config::{self, Hook},
+    generator::Document,
+};
+
+/// Extension of the build pipeline. Only the methods of the hooks listed by [`Plugin::hooks`]
+/// are called, each one receiving the output of the previous plugin. They run on a blocking
+/// thread of the runtime, so they may block.
+pub trait Plugin: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn hooks(&self) -> &[Hook];
+
+    /// Source of the page built from `page`, before it is converted.
+    fn source_loaded(&self, _page: &str, source: String) -> anyhow::Result<String> {
+        Ok(source)
+    }
+
+    /// HTML produced by asciidoctor or the Markdown renderer for `page`.
+    fn raw_html(&self, _page: &str, html: String) -> anyhow::Result<String> {
+        Ok(html)
+    }
+
+    /// Template context of `page`.
+    fn document(&self, _page: &str, document: Document) -> anyhow::Result<Document> {
+        Ok(document)
+    }
+
+    /// Final HTML of `page`, before it is written.
+    fn page_rendered(&self, _page: &str, html: String) -> anyhow::Result<String> {
+        Ok(html)
+    }
+
+    /// Called once every file of the site has been written to `output_dir`.
+    fn site_finished(&self, _output_dir: &Path) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A plugin configured under `[[plugins]]`. For every hook, the command is run with
+/// `{"hook": …, "page": …, "data": …}` on its standard input, and prints `{"data": …}` to
+/// replace the data, or nothing to keep it. `data` is a string, except for the `document` hook
+/// where it is the template context, and `site-finished` where it is the output directory.
+/// A command still running after `timeout` seconds is killed.
+#[derive(Debug)]
+pub struct ExternalPlugin {
+    config: config::Plugin,
+}
+
+impl ExternalPlugin {
+    pub fn new(config: config::Plugin) -> Self {
+        Self { config }
+    }
+
+    fn call(&self, hook: Hook, page: Option<&str>, data: Value) -> anyhow::Result<Value> {
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let request = serde_json::to_vec(&json!({
+            "hook": hook,
+            "page": page,
+            "data": data,
+        }))?;
+        // Written and read on their own threads, so that a plugin answering before it read
+        // everything does not block, and one that hangs can be killed.
+        let mut stdin = child.stdin.take().unwrap();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&request);
+        });
+        let mut stdout = child.stdout.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut res = Vec::new();
+            let _ = tx.send(stdout.read_to_end(&mut res).map(|_| res));
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(self.config.timeout);
+        let output = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(stdout) => wait_until(&mut child, deadline)?.map(|status| (status, stdout)),
+            Err(_) => None,
+        };
+        let Some((status, stdout)) = output else {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}s", self.config.timeout);
+        };
+        if !status.success() {
+            anyhow::bail!("exited with {status}");
+        }
+        let stdout = stdout?;
+        if stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(data);
+        }
+        let mut response: Value = serde_json::from_slice(&stdout)?;
+        Ok(match response.get_mut("data") {
+            Some(data) => data.take(),
+            None => data,
+        })
+    }
+
+    fn call_str(&self, hook: Hook, page: &str, data: String) -> anyhow::Result<String> {
+        match self.call(hook, Some(page), Value::String(data))? {
+            Value::String(data) => Ok(data),
+            other => anyhow::bail!("expected a string, got {other}"),
+        }
+    }
+}
+
+/// Waits for `child` to exit, returning `None` if it still runs at `deadline`.
+fn wait_until(child: &mut Child, deadline: Instant) -> std::io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+impl Plugin for ExternalPlugin {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &self.config.hooks
+    }
+
+    fn source_loaded(&self, page: &str, source: String) -> anyhow::Result<String> {
+        self.call_str(Hook::SourceLoaded, page, source)
+    }
+
+    fn raw_html(&self, page: &str, html: String) -> anyhow::Result<String> {
+        self.call_str(Hook::RawHtml, page, html)
+    }
+
+    fn document(&self, page: &str, document: Document) -> anyhow::Result<Document> {
+        let data = serde_json::to_value(&document)?;
+        let res = self.call(Hook::Document, Some(page), data)?;
+        Ok(serde_json::from_value(res)?)
+    }
+
+    fn page_rendered(&self, page: &str, html: String) -> anyhow::Result<String> {
+        self.call_str(Hook::PageRendered, page, html)
+    }
+
+    fn site_finished(&self, output_dir: &Path) -> anyhow::Result<()> {
+        let data = Value::String(output_dir.to_string_lossy().into());
+        self.call(Hook::SiteFinished, None, data)?;
+        Ok(())
+    }
+}
+
+/// The plugins of a build, run in the order they were registered on blocking threads. A plugin
+/// failing is reported and its hook skipped.
+#[derive(Debug, Default)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub fn from_config(config: &[config::Plugin]) -> Self {
+        let mut res = Self::default();
+        for plugin in config {
+            res.register(Box::new(ExternalPlugin::new(plugin.clone())));
+        }
+        res
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin.into());
+    }
+
+    /// Whether a plugin hooks into `hook`.
+    pub fn handles(&self, hook: Hook) -> bool {
+        self.plugins
+            .iter()
+            .any(|plugin| plugin.hooks().contains(&hook))
+    }
+
+    async fn run<T: Clone + Send + 'static>(
+        &self,
+        hook: Hook,
+        page: &str,
+        mut value: T,
+        f: fn(&dyn Plugin, &str, T) -> anyhow::Result<T>,
+    ) -> T {
+        for plugin in &self.plugins {
+            if !plugin.hooks().contains(&hook) {
+                continue;
+            }
+            let task = (plugin.clone(), page.to_string(), value.clone());
+            let res = tokio::task::spawn_blocking(move || {
+                let (plugin, page, input) = task;
+                f(plugin.as_ref(), &page, input)
+            })
+            .await;
+            match res {
+                Ok(Ok(res)) => value = res,
+                Ok(Err(err)) => error!("Plugin {} failed on {page}: {err}", plugin.name()),
+                Err(err) => error!("Plugin {} panicked on {page}: {err}", plugin.name()),
+            }
+        }
+        value
+    }
+
+    pub async fn source_loaded(&self, page: &str, source: String) -> String {
+        self.run(Hook::SourceLoaded, page, source, |plugin, page, source| {
+            plugin.source_loaded(page, source)
+        })
+        .await
+    }
+
+    pub async fn raw_html(&self, page: &str, html: String) -> String {
+        self.run(Hook::RawHtml, page, html, |plugin, page, html| {
+            plugin.raw_html(page, html)
+        })
+        .await
+    }
+
+    pub async fn document(&self, page: &str, document: Document) -> Document {
+        self.run(Hook::Document, page, document, |plugin, page, document| {
+            plugin.document(page, document)
+        })
+        .await
+    }
+
+    pub async fn page_rendered(&self, page: &str, html: String) -> String {
+        self.run(Hook::PageRendered, page, html, |plugin, page, html| {
+            plugin.page_rendered(page, html)
+        })
+        .await
+    }
+
+    pub async fn site_finished(&self, output_dir: &Path) {
+        let output_dir = output_dir.to_string_lossy().into_owned();
+        self.run(
+            Hook::SiteFinished,
+            &output_dir.clone(),
+            output_dir,
+            |plugin, _, output_dir| {
+                plugin.site_finished(Path::new(&output_dir))?;
+                Ok(output_dir)
+            },
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Plugin, Plugins};
+    use crate::{
+        config::{self, Hook},
+        generator::Document,
+    };
+
+    #[derive(Debug)]
+    struct Upper;
+
+    impl Plugin for Upper {
+        fn name(&self) -> &str {
+            "upper"
+        }
+
+        fn hooks(&self) -> &[Hook] {
+            &[Hook::RawHtml, Hook::Document]
+        }
+
+        fn raw_html(&self, _page: &str, html: String) -> anyhow::Result<String> {
+            Ok(html.to_uppercase())
+        }
+
+        fn document(&self, page: &str, mut document: Document) -> anyhow::Result<Document> {
+            document.title = format!("{} ({page})", document.title);
+            Ok(document)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugins() {
+        let mut plugins = Plugins::default();
+        assert!(!plugins.handles(Hook::RawHtml));
+        plugins.register(Box::new(Upper));
+        assert!(plugins.handles(Hook::RawHtml));
+        assert!(!plugins.handles(Hook::SourceLoaded));
+
+        assert_eq!(
+            plugins.raw_html("a.adoc", "<p>a</p>".into()).await,
+            "<P>A</P>"
+        );
+        assert_eq!(
+            plugins.page_rendered("a.adoc", "<p>a</p>".into()).await,
+            "<p>a</p>"
+        );
+        let document = Document {
+            title: "A".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            plugins.document("a.adoc", document).await.title,
+            "A (a.adoc)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_external_plugin() {
+        let plugin = |name: &str, script: &str| config::Plugin {
+            name: name.into(),
+            command: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            hooks: vec![Hook::RawHtml, Hook::Document, Hook::SiteFinished],
+            timeout: 1,
+        };
+        let plugins = Plugins::from_config(&[
+            plugin("keep", "cat > /dev/null"),
+            plugin(
+                "replace",
+                r#"grep -q '"hook":"raw-html"' && echo '{"data": "<p>b</p>"}'"#,
+            ),
+            plugin("fail", "cat > /dev/null; exit 1"),
+            plugin("hang", "sleep 10"),
+        ]);
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            plugins.raw_html("a.adoc", "<p>a</p>".into()).await,
+            "<p>b</p>"
+        );
+        assert!(started.elapsed().as_secs() < 5);
+        let document = Document {
+            title: "A".into(),
+            ..Default::default()
+        };
+        // `replace` exits with 1 when grep does not match, and is skipped like `fail`.
+        assert_eq!(plugins.document("a.adoc", document).await.title, "A");
+        plugins.site_finished(std::path::Path::new("public")).await;
+    }
+}
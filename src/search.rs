@@ -0,0 +1,365 @@
+use std::{collections::BTreeMap, path::Path};
+
+use pagefind::{api::PagefindIndex, options::PagefindServiceConfig};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::*;
+
+use crate::{
+    config,
+    index::CacheValue,
+    summary::Summary,
+    utils::{is_cjk, Section},
+};
+
+/// Bumped whenever the layout of the files under `search/` changes.
+pub const INDEX_VERSION: u32 = 2;
+
+const TITLE_WEIGHT: u32 = 10;
+const HEADING_WEIGHT: u32 = 5;
+const EXCERPT_LENGTH: usize = 160;
+/// Upper bound of distinct terms per shard before the index is split further.
+const TERMS_PER_SHARD: usize = 1000;
+const MAX_SHARDS: usize = 256;
+const MAX_WORD_LENGTH: usize = 64;
+
+/// Splits `text` into lowercase search terms. Words are split on anything that is not
+/// alphanumeric, runs of CJK characters become overlapping bigrams, or a single term when the run
+/// is one character long. `theme/src/worker.ts` must tokenize queries the same way.
+pub fn tokenize(text: &str) -> Vec<String> {
+    fn flush_cjk(run: &mut Vec<char>, res: &mut Vec<String>) {
+        if run.len() == 1 {
+            res.push(run[0].to_string());
+        } else {
+            res.extend(run.windows(2).map(|pair| pair.iter().collect()));
+        }
+        run.clear();
+    }
+
+    let mut res = Vec::new();
+    let mut word = String::new();
+    let mut run = Vec::new();
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                res.push(std::mem::take(&mut word));
+            }
+            run.push(c);
+            continue;
+        }
+        if !run.is_empty() {
+            flush_cjk(&mut run, &mut res);
+        }
+        if c.is_alphanumeric() {
+            if word.chars().count() < MAX_WORD_LENGTH {
+                word.extend(c.to_lowercase());
+            }
+        } else if !word.is_empty() {
+            res.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        res.push(word);
+    }
+    if !run.is_empty() {
+        flush_cjk(&mut run, &mut res);
+    }
+    res
+}
+
+/// FNV-1a over the UTF-8 bytes of `term`, used to pick the shard of a term.
+fn shard_of(term: &str, shards: usize) -> usize {
+    let hash = term.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    hash as usize % shards
+}
+
+/// A section of a page, which search hits link to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchDoc {
+    /// URL of the page, with the anchor of the section.
+    pub url: String,
+    /// Title of the page.
+    pub title: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub heading: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub breadcrumb: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    pub excerpt: String,
+    /// Only set on the first section of a page.
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// Occurrence of a term in a document, serialized as `[doc, score]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Posting(pub usize, pub u32);
+
+/// `search/index.json`: documents and the number of term shards, which live in
+/// `search/terms/<shard>.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchMeta {
+    pub version: u32,
+    pub shards: usize,
+    pub docs: Vec<SearchDoc>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    docs: Vec<SearchDoc>,
+    terms: BTreeMap<String, Vec<Posting>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index from the pages recorded with `index_insert`.
+    pub fn from_cache(items: Vec<(String, CacheValue)>) -> Self {
+        let mut res = Self::new();
+        for (url, (title, date, (summary, word_count, reading_time), sections)) in items {
+            let summary = Summary {
+                summary,
+                word_count: word_count as usize,
+                reading_time: reading_time as usize,
+            };
+            let sections = sections
+                .into_iter()
+                .map(|(id, heading, breadcrumb, text)| Section {
+                    id,
+                    heading,
+                    breadcrumb,
+                    text,
+                })
+                .collect();
+            res.add(&url, &title, date, summary, sections);
+        }
+        res
+    }
+
+    /// Adds a document for every section of a page. The title of the page only counts for its
+    /// first section, so that the page itself ranks above its sections.
+    pub fn add(
+        &mut self,
+        url: &str,
+        title: &str,
+        date: Option<String>,
+        summary: Summary,
+        sections: Vec<Section>,
+    ) {
+        let sections = if sections.is_empty() {
+            vec![Section::default()]
+        } else {
+            sections
+        };
+        for (idx, section) in sections.into_iter().enumerate() {
+            let doc = self.docs.len();
+            let mut scores: BTreeMap<String, u32> = BTreeMap::new();
+            if idx == 0 {
+                for term in tokenize(title) {
+                    *scores.entry(term).or_default() += TITLE_WEIGHT;
+                }
+            }
+            for term in tokenize(&section.heading) {
+                *scores.entry(term).or_default() += HEADING_WEIGHT;
+            }
+            for term in tokenize(&section.text) {
+                *scores.entry(term).or_default() += 1;
+            }
+            for (term, score) in scores {
+                self.terms
+                    .entry(term)
+                    .or_default()
+                    .push(Posting(doc, score));
+            }
+
+            self.docs.push(SearchDoc {
+                url: match section.id {
+                    Some(id) => format!("{url}#{id}"),
+                    None => url.into(),
+                },
+                title: title.into(),
+                heading: section.heading,
+                breadcrumb: section.breadcrumb,
+                date: date.clone(),
+                excerpt: section.text.chars().take(EXCERPT_LENGTH).collect(),
+                summary: if idx == 0 {
+                    summary.clone()
+                } else {
+                    Summary::default()
+                },
+            });
+        }
+    }
+
+    pub fn docs(&self) -> &[SearchDoc] {
+        &self.docs
+    }
+
+    /// Number of shards the terms are split into, a power of two.
+    pub fn shards(&self) -> usize {
+        self.terms
+            .len()
+            .div_ceil(TERMS_PER_SHARD)
+            .next_power_of_two()
+            .min(MAX_SHARDS)
+    }
+
+    /// Terms grouped by shard, postings sorted by descending score.
+    pub fn shard_terms(&self) -> Vec<BTreeMap<&str, Vec<Posting>>> {
+        let shards = self.shards();
+        let mut res = vec![BTreeMap::new(); shards];
+        for (term, postings) in &self.terms {
+            let mut postings = postings.clone();
+            postings.sort_by(|a, b| b.1.cmp(&a.1));
+            res[shard_of(term, shards)].insert(term.as_str(), postings);
+        }
+        res
+    }
+
+    /// Writes `index.json` and the term shards to `output_dir`, replacing a previous index.
+    pub async fn write(&self, output_dir: impl AsRef<Path>) -> anyhow::Result<usize> {
+        let output_dir = output_dir.as_ref();
+        let terms_dir = output_dir.join("terms");
+        if terms_dir.exists() {
+            fs::remove_dir_all(&terms_dir).await?;
+        }
+        fs::create_dir_all(&terms_dir).await?;
+
+        let meta = SearchMeta {
+            version: INDEX_VERSION,
+            shards: self.shards(),
+            docs: self.docs.clone(),
+        };
+        fs::write(output_dir.join("index.json"), serde_json::to_vec(&meta)?).await?;
+        for (idx, terms) in self.shard_terms().into_iter().enumerate() {
+            fs::write(
+                terms_dir.join(format!("{idx}.json")),
+                serde_json::to_vec(&terms)?,
+            )
+            .await?;
+        }
+        debug!(
+            "Search index: {} documents, {} terms",
+            self.docs.len(),
+            self.terms.len()
+        );
+        Ok(self.docs.len())
+    }
+}
+
+fn pagefind_options(config: &config::Search) -> anyhow::Result<PagefindServiceConfig> {
+    let mut exclude_selectors = config.exclude_selectors.clone();
+    if config.exclude_code {
+        exclude_selectors.push("pre".into());
+    }
+    Ok(serde_json::from_value(serde_json::json!({
+        "root_selector": config.root_selector,
+        "exclude_selectors": exclude_selectors,
+        "force_language": config.language,
+    }))?)
+}
+
+/// Indexes the HTML files of `site_dir` with pagefind and writes its bundle to `site_dir/pagefind`.
+/// Returns the number of pages indexed.
+pub async fn generate_pagefind(site_dir: &str, config: &config::Search) -> anyhow::Result<usize> {
+    let mut index = PagefindIndex::new(Some(pagefind_options(config)?))?;
+    let pages = index
+        .add_directory(site_dir.into(), config.glob.clone())
+        .await?;
+    let output = Path::new(site_dir).join("pagefind");
+    index
+        .write_files(Some(output.to_string_lossy().into()))
+        .await?;
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate_pagefind, shard_of, tokenize, Posting, SearchIndex};
+    use crate::{config, summary::Summary, utils::Section};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Hello, World! TCP_NODELAY"),
+            vec!["hello", "world", "tcp", "nodelay"]
+        );
+        assert_eq!(
+            tokenize("使用epoll实现服务器"),
+            vec!["使用", "epoll", "实现", "现服", "服务", "务器"]
+        );
+        assert_eq!(tokenize("第 1 章"), vec!["第", "1", "章"]);
+    }
+
+    #[test]
+    fn test_shard_of() {
+        // Must agree with `shardOf` in theme/src/worker.ts.
+        assert_eq!(shard_of("", 256), 0x811c9dc5 % 256);
+        assert_eq!(shard_of("a", usize::MAX), 0xe40c292c);
+    }
+
+    #[tokio::test]
+    async fn test_generate_pagefind() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root).unwrap();
+        std::fs::write(
+            root.join("index.html"),
+            "<html lang=\"en\"><body><h1>Sockets</h1><p>Non-blocking sockets.</p></body></html>",
+        )
+        .unwrap();
+
+        let config = config::Search {
+            exclude_selectors: vec!["nav".into()],
+            ..Default::default()
+        };
+        let pages = generate_pagefind(root.to_str().unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(pages, 1);
+        assert!(root.join("pagefind/pagefind.js").exists());
+    }
+
+    #[test]
+    fn test_index() {
+        let mut index = SearchIndex::new();
+        index.add(
+            "a.html",
+            "套接字",
+            None,
+            Summary {
+                word_count: 9,
+                ..Default::default()
+            },
+            vec![
+                Section {
+                    text: "非阻塞的套接字".into(),
+                    ..Default::default()
+                },
+                Section {
+                    id: Some("_epoll".into()),
+                    heading: "Epoll".into(),
+                    text: "边缘触发".into(),
+                    ..Default::default()
+                },
+            ],
+        );
+        index.add("b.html", "Other", None, Default::default(), vec![]);
+        assert_eq!(index.shards(), 1);
+        assert_eq!(index.docs()[1].url, "a.html#_epoll");
+        assert_eq!(index.docs()[2].url, "b.html");
+
+        let terms = index.shard_terms().remove(0);
+        assert_eq!(terms["epoll"], vec![Posting(1, 5)]);
+        assert_eq!(terms["套接"], vec![Posting(0, 11)]);
+        assert_eq!(terms["other"], vec![Posting(2, 10)]);
+        assert_eq!(index.docs()[0].excerpt, "非阻塞的套接字");
+        assert_eq!(index.docs()[0].summary.word_count, 9);
+        assert_eq!(index.docs()[1].summary.word_count, 0);
+    }
+}
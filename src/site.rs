@@ -0,0 +1,419 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+};
+
+use lazy_regex::regex;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{
+    generator::BuildContext,
+    source::SourceFormat,
+    summary::{Summarizer, Summary},
+    url::UrlBuilder,
+    utils::{GitInfo, HtmlParser},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub title: String,
+    pub url: String,
+}
+
+/// Neighbours of a page in the reading order defined by the index files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Navigation {
+    pub prev: Option<Link>,
+    pub next: Option<Link>,
+    pub siblings: Vec<Link>,
+    pub children: Vec<Link>,
+}
+
+/// A node of the xref graph rooted at `content/index.adoc`.
+#[derive(Debug, Clone)]
+pub struct IndexNode {
+    pub source_file: String,
+    pub children: Vec<IndexNode>,
+}
+
+impl IndexNode {
+    /// Parses `file_path` and, if it is an `index.adoc`, every file it references through xrefs.
+    /// Files referenced more than once only appear at their first position.
+    pub fn parse(file_path: &str) -> Option<Self> {
+        Self::parse_inner(file_path, &mut HashSet::new())
+    }
+
+    fn parse_inner(file_path_str: &str, visited: &mut HashSet<String>) -> Option<Self> {
+        debug!("process file: {}", file_path_str);
+
+        let file_path = Path::new(file_path_str);
+        if !file_path.exists() {
+            warn!("file doesn't existes: {}", file_path.display());
+            return None;
+        }
+        if !visited.insert(file_path_str.to_string()) {
+            return None;
+        }
+
+        let mut children = Vec::new();
+        if file_path.ends_with("index.adoc") {
+            let dir_path = file_path.parent().unwrap();
+            let content = std::fs::read_to_string(file_path).unwrap_or_default();
+
+            let re = regex!(r"xref:([^\[#\s]+)(#[^\[\s]*)?\[");
+            for item in re.captures_iter(&content) {
+                let file_name: String = item.get(1).unwrap().as_str().replace("{cpp}", "c++");
+                let file_path = normalize(&dir_path.join(file_name));
+                let Some(file_path) = file_path.to_str() else {
+                    continue;
+                };
+                if let Some(child) = Self::parse_inner(file_path, visited) {
+                    children.push(child);
+                }
+            }
+        }
+
+        Some(Self {
+            source_file: file_path_str.into(),
+            children,
+        })
+    }
+
+    /// All files of the tree in reading order: an index page comes before its children.
+    pub fn files(&self) -> Vec<String> {
+        let mut res = vec![self.source_file.clone()];
+        for child in &self.children {
+            res.extend(child.files());
+        }
+        res
+    }
+}
+
+/// Resolves `.` and `..` without touching the file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(res.components().next_back(), Some(Component::Normal(_))) {
+                    res.pop();
+                } else if !res.has_root() {
+                    res.push("..");
+                }
+            }
+            other => res.push(other),
+        }
+    }
+    res
+}
+
+/// A page that has been converted to HTML.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub source_file: String,
+    pub dest_file: String,
+    /// Output path relative to the site root.
+    pub path: String,
+    pub title: String,
+    /// Attributes declared in the document header.
+    pub attributes: HashMap<String, String>,
+    pub last_modified: Option<String>,
+    pub summary: Summary,
+}
+
+impl Page {
+    /// Registers a page from the HTML generated for it, which is the single source of its title.
+    pub async fn new(
+        ctx: &BuildContext,
+        html: &str,
+        urls: &UrlBuilder,
+        gitinfo: &GitInfo,
+        summarizer: &Summarizer,
+    ) -> Self {
+        let html = HtmlParser::new(html);
+        let format = SourceFormat::from_path(&ctx.source_file).unwrap_or(SourceFormat::Asciidoc);
+        let attributes = std::fs::read_to_string(&ctx.source_file)
+            .map(|content| format.attributes(&content))
+            .unwrap_or_default();
+        let summary =
+            summarizer.summarize(&html, attributes.get("description").map(String::as_str));
+        Self {
+            source_file: ctx.source_file.clone(),
+            dest_file: ctx.dest_file.clone(),
+            path: urls.path(&ctx.dest_file),
+            title: html.get_title(),
+            attributes,
+            last_modified: gitinfo.get_last_commit_time_of_file(&ctx.source_file).await,
+            summary,
+        }
+    }
+
+    /// Tags listed in the `:tags:` attribute or the `tags` front matter, separated by commas.
+    pub fn tags(&self) -> Vec<String> {
+        self.attributes
+            .get("tags")
+            .map(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Registry of all the pages built in this run, keyed by source file.
+#[derive(Debug, Default)]
+pub struct Site {
+    /// Directory of the entry `index.adoc`.
+    root_dir: PathBuf,
+    root: Option<IndexNode>,
+    urls: UrlBuilder,
+    pages: HashMap<String, Page>,
+    /// Built pages in reading order.
+    order: Vec<String>,
+    parents: HashMap<String, String>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl Site {
+    pub fn new(root: Option<&IndexNode>, pages: Vec<Page>, urls: UrlBuilder) -> Self {
+        let mut site = Self {
+            urls,
+            pages: pages
+                .into_iter()
+                .map(|page| (page.source_file.clone(), page))
+                .collect(),
+            ..Default::default()
+        };
+        if let Some(root) = root {
+            site.root_dir = Path::new(&root.source_file)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            site.visit(root);
+        }
+        site.root = root.cloned();
+        site
+    }
+
+    /// The xref graph the site has been built from.
+    pub fn index(&self) -> Option<&IndexNode> {
+        self.root.as_ref()
+    }
+
+    fn visit(&mut self, node: &IndexNode) {
+        if self.pages.contains_key(&node.source_file) {
+            self.order.push(node.source_file.clone());
+        }
+        let children: Vec<String> = node
+            .children
+            .iter()
+            .filter(|child| self.pages.contains_key(&child.source_file))
+            .map(|child| child.source_file.clone())
+            .collect();
+        for child in &children {
+            self.parents.insert(child.clone(), node.source_file.clone());
+        }
+        self.children.insert(node.source_file.clone(), children);
+
+        for child in &node.children {
+            self.visit(child);
+        }
+    }
+
+    pub fn page(&self, source_file: &str) -> Option<&Page> {
+        self.pages.get(source_file)
+    }
+
+    /// Built pages in reading order.
+    pub fn pages(&self) -> impl Iterator<Item = &Page> {
+        self.order.iter().filter_map(|item| self.page(item))
+    }
+
+    pub fn urls(&self) -> &UrlBuilder {
+        &self.urls
+    }
+
+    /// Link to the page built from `target`, as seen from the page built from `from`.
+    pub fn link(&self, from: &str, target: &str) -> Option<Link> {
+        let from = self.page(from).map(|page| page.path.as_str()).unwrap_or("");
+        self.page(target).map(|page| Link {
+            title: page.title.clone(),
+            url: self.urls.page(from, &page.path),
+        })
+    }
+
+    fn links(&self, from: &str, source_files: &[String]) -> Vec<Link> {
+        source_files
+            .iter()
+            .filter_map(|item| self.link(from, item))
+            .collect()
+    }
+
+    /// Breadcrumbs of a page as `(title, url)` pairs: the index page of every directory between
+    /// the content root and the page. Directories without a built index page are skipped.
+    pub fn ancestors(&self, source_file: &str) -> Vec<(String, String)> {
+        let mut dirs: Vec<&Path> = Path::new(source_file)
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != self.root_dir && dir.starts_with(&self.root_dir))
+            .collect();
+        dirs.reverse();
+
+        dirs.into_iter()
+            .filter_map(|dir| dir.join("index.adoc").to_str().map(String::from))
+            .filter(|index| index != source_file)
+            .filter_map(|index| self.link(source_file, &index))
+            .map(|link| (link.title, link.url))
+            .collect()
+    }
+
+    pub fn navigation(&self, source_file: &str) -> Navigation {
+        let mut res = Navigation::default();
+        if let Some(idx) = self.order.iter().position(|item| item == source_file) {
+            if idx > 0 {
+                res.prev = self.link(source_file, &self.order[idx - 1]);
+            }
+            res.next = self
+                .order
+                .get(idx + 1)
+                .and_then(|item| self.link(source_file, item));
+        }
+        if let Some(parent) = self.parents.get(source_file) {
+            let siblings: Vec<String> = self.children[parent]
+                .iter()
+                .filter(|item| *item != source_file)
+                .cloned()
+                .collect();
+            res.siblings = self.links(source_file, &siblings);
+        }
+        if let Some(children) = self.children.get(source_file) {
+            res.children = self.links(source_file, children);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{normalize, IndexNode, Link, Page, Site};
+    use crate::url::UrlBuilder;
+
+    #[test]
+    fn test_normalize() {
+        let normalize = |path: &str| normalize(Path::new(path));
+        assert_eq!(
+            normalize("content/./a/../b.adoc"),
+            Path::new("content/b.adoc")
+        );
+        assert_eq!(normalize("a/../../b"), Path::new("../b"));
+        assert_eq!(
+            normalize("a/../../../content/b"),
+            Path::new("../../content/b")
+        );
+        assert_eq!(normalize("/a/../../b"), Path::new("/b"));
+    }
+
+    fn node(source_file: &str, children: Vec<IndexNode>) -> IndexNode {
+        IndexNode {
+            source_file: source_file.into(),
+            children,
+        }
+    }
+
+    fn page(source_file: &str, title: &str) -> Page {
+        Page {
+            source_file: source_file.into(),
+            dest_file: source_file.replace("content", "public"),
+            path: source_file
+                .replace("content/", "")
+                .replace(".adoc", ".html"),
+            title: title.into(),
+            attributes: Default::default(),
+            last_modified: None,
+            summary: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_navigation() {
+        let root = node(
+            "content/index.adoc",
+            vec![
+                node(
+                    "content/a/index.adoc",
+                    vec![
+                        node("content/a/1.adoc", vec![]),
+                        node("content/a/2.adoc", vec![]),
+                    ],
+                ),
+                node("content/b.adoc", vec![]),
+            ],
+        );
+        let site = Site::new(
+            Some(&root),
+            vec![
+                page("content/index.adoc", "Home"),
+                page("content/a/index.adoc", "A"),
+                page("content/a/1.adoc", "A1"),
+                page("content/a/2.adoc", "A2"),
+                page("content/b.adoc", "B"),
+            ],
+            UrlBuilder::default(),
+        );
+
+        let nav = site.navigation("content/a/2.adoc");
+        assert_eq!(nav.prev.unwrap().title, "A1");
+        assert_eq!(nav.next.unwrap().title, "B");
+        assert_eq!(
+            nav.siblings,
+            vec![Link {
+                title: "A1".into(),
+                url: "/a/1.html".into()
+            }]
+        );
+        assert!(nav.children.is_empty());
+
+        let nav = site.navigation("content/a/index.adoc");
+        assert_eq!(nav.prev.unwrap().title, "Home");
+        assert_eq!(nav.next.unwrap().title, "A1");
+        assert_eq!(nav.children.len(), 2);
+
+        let nav = site.navigation("content/index.adoc");
+        assert!(nav.prev.is_none());
+        assert_eq!(nav.children.len(), 2);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let root = node(
+            "content/index.adoc",
+            vec![node(
+                "content/a/index.adoc",
+                vec![node("content/a/b/c.adoc", vec![])],
+            )],
+        );
+        let site = Site::new(
+            Some(&root),
+            vec![
+                page("content/index.adoc", "Home"),
+                page("content/a/index.adoc", "Chapter A"),
+                page("content/a/b/c.adoc", "C"),
+            ],
+            UrlBuilder::new("/docs", true),
+        );
+
+        assert_eq!(
+            site.ancestors("content/a/b/c.adoc"),
+            vec![("Chapter A".to_string(), "../index.html".to_string())]
+        );
+        assert!(site.ancestors("content/a/index.adoc").is_empty());
+        assert!(site.ancestors("content/index.adoc").is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+use tokio::fs;
+use tracing::*;
+
+use crate::site::Site;
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn render_sitemap(site: &Site) -> String {
+    let mut res = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in site.pages() {
+        res.push_str(&format!(
+            "  <url><loc>{}</loc></url>\n",
+            escape(&site.urls().absolute(&page.path))
+        ));
+    }
+    res.push_str("</urlset>\n");
+    res
+}
+
+/// Writes `sitemap.xml`. Sitemaps need fully qualified URLs, so nothing is written unless `site`
+/// is configured as a full URL.
+pub async fn generate_sitemap(site: &Site, output_path: &str) -> anyhow::Result<bool> {
+    if !site.urls().has_origin() {
+        debug!("`site` is not a full URL, skip sitemap.");
+        return Ok(false);
+    }
+    fs::write(output_path, render_sitemap(site)).await?;
+    Ok(true)
+}
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{markdown, utils::adoc};
+
+/// Formats pages can be written in, told apart by their extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// `.adoc` and `.asciidoc` files, converted by asciidoctor.
+    Asciidoc,
+    /// `.md` and `.markdown` files: CommonMark with GFM tables, rendered by asphinx.
+    Markdown,
+}
+
+impl SourceFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "adoc" | "asciidoc" => Some(Self::Asciidoc),
+            "md" | "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    /// Attributes declared by a page: the header attributes of AsciiDoc, the front matter of
+    /// Markdown.
+    pub fn attributes(self, content: &str) -> HashMap<String, String> {
+        match self {
+            Self::Asciidoc => adoc::header_attributes(content),
+            Self::Markdown => markdown::front_matter(content).0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SourceFormat;
+
+    #[test]
+    fn test_source_format() {
+        assert_eq!(
+            SourceFormat::from_path("content/a.adoc"),
+            Some(SourceFormat::Asciidoc)
+        );
+        assert_eq!(
+            SourceFormat::from_path("content/b.md"),
+            Some(SourceFormat::Markdown)
+        );
+        assert_eq!(SourceFormat::from_path("content/c.txt"), None);
+        assert_eq!(
+            SourceFormat::Markdown.attributes("---\ntags: [a, b]\n---\n")["tags"],
+            "a, b"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config,
+    utils::{is_cjk, HtmlParser, TextFilter},
+};
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// Short description and length of a page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub word_count: usize,
+    /// Minutes, rounded up.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reading_time: usize,
+}
+
+/// Counts of `(words, CJK characters)` in `text`. Each CJK character counts as a word of its own.
+pub fn count_words(text: &str) -> (usize, usize) {
+    let mut words = 0;
+    let mut cjk = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() {
+            if !in_word {
+                words += 1;
+            }
+            in_word = true;
+        } else {
+            in_word = in_word && !c.is_whitespace();
+        }
+    }
+    (words, cjk)
+}
+
+/// Shortens `text` to at most `length` characters, cutting between words or after a CJK
+/// character, and marks the cut with an ellipsis.
+pub fn truncate(text: &str, length: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= length {
+        return text.into();
+    }
+
+    let boundary = |idx: usize| {
+        chars[idx].is_whitespace()
+            || chars[idx - 1].is_whitespace()
+            || is_cjk(chars[idx])
+            || is_cjk(chars[idx - 1])
+    };
+    let end = (1..=length)
+        .rev()
+        .find(|idx| boundary(*idx))
+        .unwrap_or(length);
+    let mut res: String = chars[..end].iter().collect();
+    res.truncate(res.trim_end().len());
+    res.push('…');
+    res
+}
+
+/// Derives the [`Summary`] of pages as configured in `[summary]`.
+#[derive(Debug, Clone)]
+pub struct Summarizer {
+    filter: TextFilter,
+    config: config::Summary,
+}
+
+impl Summarizer {
+    pub fn new(config: &config::Config) -> Self {
+        Self {
+            filter: TextFilter::new(&config.search.exclude_selectors, config.search.exclude_code),
+            config: config.summary.clone(),
+        }
+    }
+
+    /// `description` is the `:description:` attribute of the page, which takes precedence over
+    /// the first paragraph.
+    pub fn summarize(&self, html: &HtmlParser, description: Option<&str>) -> Summary {
+        let summary = description
+            .map(|item| item.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|item| !item.is_empty())
+            .or_else(|| html.first_paragraph(&self.filter))
+            .map(|item| truncate(&item, self.config.length));
+
+        let (words, cjk) = count_words(&html.text(&self.filter));
+        let minutes = words as f64 / self.config.words_per_minute.max(1) as f64
+            + cjk as f64 / self.config.cjk_per_minute.max(1) as f64;
+        Summary {
+            summary,
+            word_count: words + cjk,
+            reading_time: minutes.ceil() as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_words, truncate, Summarizer, Summary};
+    use crate::{config::Config, utils::HtmlParser};
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("hello wonderful world", 12), "hello…");
+        assert_eq!(truncate("hello wonderful world", 15), "hello wonderful…");
+        assert_eq!(truncate("非阻塞的套接字", 4), "非阻塞的…");
+        assert_eq!(truncate("使用epoll实现", 5), "使用…");
+        assert_eq!(truncate("unbreakable", 4), "unbr…");
+    }
+
+    #[test]
+    fn test_count_words() {
+        assert_eq!(count_words("Hello, world! it's fine"), (4, 0));
+        assert_eq!(count_words("使用 epoll 实现"), (1, 4));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut config = Config::default();
+        config.summary.length = 8;
+        config.summary.words_per_minute = 2;
+        let summarizer = Summarizer::new(&config);
+        let html = HtmlParser::new(
+            r#"<body><div id="content"><div id="preamble"><div class="sectionbody">
+            <div class="paragraph"><p>First paragraph here.</p></div>
+            <div class="paragraph"><p>Second.</p></div></div></div></div></body>"#,
+        );
+
+        assert_eq!(
+            summarizer.summarize(&html, None),
+            Summary {
+                summary: Some("First…".into()),
+                word_count: 4,
+                reading_time: 2,
+            }
+        );
+        assert_eq!(
+            summarizer.summarize(&html, Some("  套接字 ")).summary,
+            Some("套接字".into())
+        );
+    }
+}
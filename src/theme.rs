@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use toml::Table;
+use tracing::*;
+
+use crate::{config, error::Error};
+
+/// Name used by `parent` to refer to the theme embedded in the binary.
+pub static EMBEDDED_THEME: &str = "default";
+
+static EMBEDDED_MANIFEST: &str = include_str!("theme/default/theme.toml");
+/// Layout of exported books, also used with themes that do not provide one.
+pub const BOOK_LAYOUT: &str = include_str!("theme/default/layouts/book.html");
+static EMBEDDED_LAYOUTS: &[(&str, &str)] = &[
+    ("page", include_str!("theme/default/layouts/page.html")),
+    ("nav", include_str!("theme/default/layouts/nav.html")),
+    ("book", BOOK_LAYOUT),
+];
+static EMBEDDED_ASSETS: &[(&str, &str)] = &[(
+    "asphinx.css",
+    include_str!("theme/default/assets/asphinx.css"),
+)];
+
+/// Contents of `theme.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeManifest {
+    pub name: String,
+    pub parent: Option<String>,
+    /// Params the theme understands, together with their default values.
+    pub params: Table,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThemeSource {
+    Dir(PathBuf),
+    Embedded,
+}
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    /// Themes ordered from the selected one up to the root of the inheritance chain.
+    pub chain: Vec<ThemeSource>,
+    /// Declared params merged with the overrides from `[theme.params]`.
+    pub params: Table,
+}
+
+impl Theme {
+    /// Loads the theme at `theme_dir` and all of its parents, falling back to the embedded theme
+    /// when no directory is given.
+    pub fn load(theme_dir: Option<&str>, config: &config::Theme) -> Result<Self, Error> {
+        let mut chain = Vec::new();
+        let mut manifests = Vec::new();
+        let mut visited = HashSet::new();
+
+        let mut next = match theme_dir {
+            Some(dir) => ThemeSource::Dir(PathBuf::from(dir)),
+            None => ThemeSource::Embedded,
+        };
+        loop {
+            let manifest = Self::read_manifest(&next)?;
+            let parent = match (&next, &manifest.parent) {
+                (_, None) => None,
+                (_, Some(parent)) if parent == EMBEDDED_THEME => Some(ThemeSource::Embedded),
+                (ThemeSource::Dir(dir), Some(parent)) => {
+                    Some(ThemeSource::Dir(Self::resolve_parent(dir, parent)?))
+                }
+                (ThemeSource::Embedded, Some(_)) => None,
+            };
+
+            let key = match &next {
+                ThemeSource::Dir(dir) => dir.canonicalize().unwrap_or(dir.clone()),
+                ThemeSource::Embedded => PathBuf::from(EMBEDDED_THEME),
+            };
+            if !visited.insert(key) {
+                return Err(Error::InvalidTheme(
+                    manifest.name,
+                    "theme inheritance contains a cycle".into(),
+                ));
+            }
+
+            chain.push(next);
+            manifests.push(manifest);
+            match parent {
+                Some(parent) => next = parent,
+                None => break,
+            }
+        }
+
+        // Children override the defaults declared by their parents.
+        let mut params = Table::new();
+        for manifest in manifests.iter().rev() {
+            params.extend(manifest.params.clone());
+        }
+        for (key, value) in &config.params {
+            if !params.contains_key(key) {
+                warn!("Theme param `{key}` is not declared by the theme.");
+            }
+            params.insert(key.clone(), value.clone());
+        }
+
+        let name = manifests.swap_remove(0).name;
+        debug!("Load theme {name}: {chain:?}");
+        Ok(Self {
+            name,
+            chain,
+            params,
+        })
+    }
+
+    fn read_manifest(source: &ThemeSource) -> Result<ThemeManifest, Error> {
+        let (name, content) = match source {
+            ThemeSource::Embedded => (EMBEDDED_THEME.to_string(), EMBEDDED_MANIFEST.to_string()),
+            ThemeSource::Dir(dir) => {
+                if !dir.is_dir() {
+                    return Err(Error::NotFound(dir.display().to_string()));
+                }
+                let name = dir.display().to_string();
+                match std::fs::read_to_string(dir.join("theme.toml")) {
+                    Ok(content) => (name, content),
+                    // A theme without manifest has neither parent nor params.
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(ThemeManifest {
+                            name,
+                            ..Default::default()
+                        });
+                    }
+                    Err(err) => return Err(Error::InvalidTheme(name, err.to_string())),
+                }
+            }
+        };
+
+        let mut manifest = toml::from_str::<ThemeManifest>(&content)
+            .map_err(|err| Error::InvalidTheme(name.clone(), err.to_string()))?;
+        if manifest.name.is_empty() {
+            manifest.name = name;
+        }
+        Ok(manifest)
+    }
+
+    /// Parents are looked up next to the child theme first, then relative to the working directory.
+    fn resolve_parent(theme_dir: &Path, parent: &str) -> Result<PathBuf, Error> {
+        let sibling = theme_dir.join("..").join(parent);
+        if sibling.is_dir() {
+            return Ok(sibling);
+        }
+        let path = PathBuf::from(parent);
+        if path.is_dir() {
+            return Ok(path);
+        }
+        Err(Error::NotFound(parent.into()))
+    }
+
+    /// Directories searched for templates, from the highest priority to the lowest. The
+    /// project-local `layouts/` always wins over the theme.
+    pub fn layout_dirs(&self) -> Vec<PathBuf> {
+        let mut res = vec![PathBuf::from("layouts")];
+        res.extend(self.chain.iter().filter_map(|source| match source {
+            ThemeSource::Dir(dir) => Some(dir.join("layouts")),
+            ThemeSource::Embedded => None,
+        }));
+        res
+    }
+
+    pub fn embedded_layout(&self, name: &str) -> Option<&'static str> {
+        if !self.chain.contains(&ThemeSource::Embedded) {
+            return None;
+        }
+        EMBEDDED_LAYOUTS
+            .iter()
+            .find(|(layout, _)| *layout == name)
+            .map(|(_, content)| *content)
+    }
+
+    /// Files of the `assets/` directories of every theme in the chain, keyed by their path
+    /// relative to it. Children override the files of their parents.
+    pub fn assets(&self) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+        let mut res = BTreeMap::new();
+        for source in self.chain.iter().rev() {
+            match source {
+                ThemeSource::Embedded => {
+                    for (name, content) in EMBEDDED_ASSETS {
+                        res.insert(name.to_string(), content.as_bytes().to_vec());
+                    }
+                }
+                ThemeSource::Dir(dir) => {
+                    let asset_path = dir.join("assets");
+                    if asset_path.is_dir() {
+                        Self::read_assets(&asset_path, "", &mut res).map_err(|err| {
+                            Error::InvalidTheme(dir.display().to_string(), err.to_string())
+                        })?;
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    fn read_assets(
+        dir: &Path,
+        prefix: &str,
+        res: &mut BTreeMap<String, Vec<u8>>,
+    ) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                Self::read_assets(&entry.path(), &format!("{path}/"), res)?;
+            } else {
+                res.insert(path, std::fs::read(entry.path())?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use toml::Table;
+
+    use super::{Theme, ThemeSource};
+    use crate::config;
+
+    fn write_theme(dir: &std::path::Path, manifest: &str) {
+        std::fs::create_dir_all(dir.join("layouts")).unwrap();
+        std::fs::write(dir.join("theme.toml"), manifest).unwrap();
+    }
+
+    #[test]
+    fn test_embedded_theme() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        assert_eq!(theme.name, "default");
+        assert_eq!(theme.chain, vec![ThemeSource::Embedded]);
+        assert!(theme.embedded_layout("page").is_some());
+        assert_eq!(
+            theme.params.get("site_title").and_then(|v| v.as_str()),
+            Some("Asphinx")
+        );
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_theme(
+            &root.join("base"),
+            r#"
+name = "base"
+parent = "default"
+[params]
+color = "red"
+logo = "base.svg"
+"#,
+        );
+        write_theme(
+            &root.join("child"),
+            r#"
+name = "child"
+parent = "base"
+[params]
+color = "blue"
+"#,
+        );
+
+        let config = config::Theme {
+            params: toml::from_str::<Table>(r#"logo = "project.svg""#).unwrap(),
+        };
+        std::fs::create_dir_all(root.join("base/assets")).unwrap();
+        std::fs::create_dir_all(root.join("child/assets/img")).unwrap();
+        std::fs::write(root.join("base/assets/asphinx.css"), "base").unwrap();
+        std::fs::write(root.join("child/assets/img/logo.svg"), "svg").unwrap();
+
+        let theme = Theme::load(root.join("child").to_str(), &config).unwrap();
+        let assets = theme.assets().unwrap();
+
+        assert_eq!(assets.len(), 2);
+        assert_eq!(assets["asphinx.css"], b"base");
+        assert_eq!(assets["img/logo.svg"], b"svg");
+
+        assert_eq!(theme.name, "child");
+        assert_eq!(theme.chain.len(), 3);
+        assert_eq!(theme.chain[2], ThemeSource::Embedded);
+        assert_eq!(theme.layout_dirs()[0], std::path::PathBuf::from("layouts"));
+        assert_eq!(theme.params.get("color").unwrap().as_str(), Some("blue"));
+        assert_eq!(
+            theme.params.get("logo").unwrap().as_str(),
+            Some("project.svg")
+        );
+        assert_eq!(
+            theme.params.get("site_title").unwrap().as_str(),
+            Some("Asphinx")
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::{Component, Path};
+
+use crate::config::Config;
+
+/// Builds every URL asphinx emits, from paths relative to the output directory.
+///
+/// Links between pages are either absolute paths under the configured base (`/docs/a.html`) or,
+/// with `relative_urls`, relative to the page containing them (`../a.html`) so that the site can
+/// be browsed from `file://`.
+#[derive(Debug, Clone)]
+pub struct UrlBuilder {
+    /// Scheme and host of `site`, e.g. `https://corp.example`.
+    origin: Option<String>,
+    /// Path prefix of `site`, always starting and ending with `/`.
+    base: String,
+    relative: bool,
+}
+
+impl Default for UrlBuilder {
+    fn default() -> Self {
+        Self::new("", false)
+    }
+}
+
+impl UrlBuilder {
+    /// `site` is either a full URL such as `https://corp.example/docs/team-a/` or a path prefix
+    /// such as `/docs/team-a`.
+    pub fn new(site: &str, relative: bool) -> Self {
+        let (origin, path) = match site.split_once("://") {
+            Some((scheme, rest)) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                (Some(format!("{scheme}://{host}")), path)
+            }
+            None => (None, site),
+        };
+        let path = path.trim_matches('/');
+        let base = if path.is_empty() || path == "." {
+            "/".into()
+        } else {
+            format!("/{path}/")
+        };
+
+        Self {
+            origin,
+            base,
+            relative,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.site, config.relative_urls)
+    }
+
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    /// Canonical form of an output path, relative to the site root. This is what site-wide data
+    /// files (file tree, search index) contain; clients resolve it against [`Self::root`].
+    pub fn path(&self, target: &str) -> String {
+        let target = target.strip_prefix("public/").unwrap_or(target);
+        let mut res = Vec::new();
+        for component in Path::new(target).components() {
+            match component {
+                Component::Normal(item) => res.push(item.to_string_lossy()),
+                Component::ParentDir => {
+                    res.pop();
+                }
+                _ => {}
+            }
+        }
+        let mut res = res.join("/");
+        if target.ends_with('/') && !res.is_empty() {
+            res.push('/');
+        }
+        res
+    }
+
+    /// URL of the site root as seen from the page at `from`.
+    pub fn root(&self, from: &str) -> String {
+        if !self.relative {
+            return self.base.clone();
+        }
+        let depth = self.path(from).matches('/').count();
+        if depth == 0 {
+            "./".into()
+        } else {
+            "../".repeat(depth)
+        }
+    }
+
+    /// URL of `target` for a link contained in the page at `from`.
+    pub fn page(&self, from: &str, target: &str) -> String {
+        let target = self.path(target);
+        if !self.relative {
+            return format!("{}{}", self.base, target);
+        }
+
+        let from = self.path(from);
+        let from_dir: Vec<&str> = from.split('/').collect();
+        let from_dir = &from_dir[..from_dir.len() - 1];
+        let target_parts: Vec<&str> = target.split('/').collect();
+        let common = from_dir
+            .iter()
+            .zip(&target_parts)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut res = "../".repeat(from_dir.len() - common);
+        res.push_str(&target_parts[common..].join("/"));
+        if res.is_empty() {
+            res.push_str("./");
+        }
+        res
+    }
+
+    /// Fully qualified URL of `target`, including the origin when `site` is a full URL.
+    pub fn absolute(&self, target: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.origin.as_deref().unwrap_or_default(),
+            self.base,
+            self.path(target)
+        )
+    }
+
+    pub fn has_origin(&self) -> bool {
+        self.origin.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UrlBuilder;
+
+    #[test]
+    fn test_absolute_urls() {
+        let urls = UrlBuilder::new("https://corp.example/docs/team-a/", false);
+        assert_eq!(
+            urls.page("public/a/b.html", "public/c/index.html"),
+            "/docs/team-a/c/index.html"
+        );
+        assert_eq!(urls.root("a/b.html"), "/docs/team-a/");
+        assert_eq!(
+            urls.absolute("a/b.html"),
+            "https://corp.example/docs/team-a/a/b.html"
+        );
+        assert!(urls.has_origin());
+
+        let urls = UrlBuilder::new(".", false);
+        assert_eq!(urls.page("a/b.html", "index.html"), "/index.html");
+        assert_eq!(urls.absolute("a/b.html"), "/a/b.html");
+    }
+
+    #[test]
+    fn test_relative_urls() {
+        let urls = UrlBuilder::new("/docs", true);
+        assert_eq!(urls.page("a/b/c.html", "a/d.html"), "../d.html");
+        assert_eq!(urls.page("a/b/c.html", "a/b/e.html"), "e.html");
+        assert_eq!(urls.page("index.html", "a/b.html"), "a/b.html");
+        assert_eq!(urls.page("a/b.html", "index.html"), "../index.html");
+        assert_eq!(urls.root("a/b/c.html"), "../../");
+        assert_eq!(urls.root("index.html"), "./");
+    }
+
+    #[test]
+    fn test_path() {
+        let urls = UrlBuilder::default();
+        assert_eq!(urls.path("public/a/./b/../c.html"), "a/c.html");
+        assert_eq!(urls.path("/assets/style.css"), "assets/style.css");
+    }
+}
@@ -1,3 +1,4 @@
+pub mod adoc;
 mod asciidoctor_builder;
 mod git;
 mod html;
@@ -10,6 +11,35 @@ pub use git::*;
 pub use html::*;
 pub use tmpl::*;
 
+/// Escapes `text` for HTML, in text as well as in attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Turns the escaped text of HTML produced by asciidoctor back into plain text.
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Characters of scripts written without spaces between words: Japanese kana, CJK ideographs and
+/// Hangul. Search indexes them as bigrams and summaries count them one by one.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2A6DF}')
+}
+
 pub fn cpu_num() -> usize {
     std::thread::available_parallelism()
         .map(|item| item.get())
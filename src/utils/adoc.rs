@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Reads the attribute entries (`:name: value`) of an AsciiDoc document header.
+///
+/// Only the header is considered: parsing stops at the first blank line following the document
+/// title, or at the first line that cannot be part of a header.
+pub fn header_attributes(content: &str) -> HashMap<String, String> {
+    let mut res = HashMap::new();
+    let mut seen_title = false;
+    let mut in_comment = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim_end();
+        if trimmed == "////" {
+            in_comment = !in_comment;
+            continue;
+        }
+        if in_comment || trimmed.starts_with("//") {
+            continue;
+        }
+        if trimmed.is_empty() {
+            if seen_title {
+                break;
+            }
+            continue;
+        }
+
+        if let Some(entry) = trimmed.strip_prefix(':') {
+            if let Some((name, value)) = entry.split_once(':') {
+                let value = value.trim();
+                if let Some(name) = name.strip_suffix('!') {
+                    res.remove(name);
+                } else if let Some(name) = name.strip_prefix('!') {
+                    res.remove(name);
+                } else {
+                    res.insert(name.to_string(), value.to_string());
+                }
+                continue;
+            }
+        }
+        if trimmed.starts_with("= ") && !seen_title {
+            seen_title = true;
+            continue;
+        }
+        // Author and revision lines directly follow the title.
+        if seen_title {
+            continue;
+        }
+        break;
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::header_attributes;
+
+    #[test]
+    fn test_header_attributes() {
+        let res = header_attributes(
+            r#"// leading comment
+:doctype: book
+= Title
+Author Name <author@example.com>
+:nav-order: 3
+////
+:commented: yes
+////
+:tags: a, b
+:unset!:
+
+:body-attr: ignored
+"#,
+        );
+        assert_eq!(res.get("doctype").unwrap(), "book");
+        assert_eq!(res.get("nav-order").unwrap(), "3");
+        assert_eq!(res.get("tags").unwrap(), "a, b");
+        assert!(!res.contains_key("commented"));
+        assert!(!res.contains_key("body-attr"));
+        assert!(!res.contains_key("unset"));
+    }
+}
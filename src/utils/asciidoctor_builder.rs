@@ -1,10 +1,36 @@
-use tokio::process;
+use std::{path::Path, sync::OnceLock};
+
+use std::process::Stdio;
+
+use tokio::{io::AsyncWriteExt, process};
+use tracing::*;
+
+static EXTENSION: &str = include_str!("asphinx.rb");
+static EXTENSION_PATH: &str = ".cache/asphinx.rb";
+
+/// Writes the extension asphinx loads into asciidoctor, once per build.
+fn extension_path() -> Option<&'static str> {
+    static WRITTEN: OnceLock<bool> = OnceLock::new();
+    let written = *WRITTEN.get_or_init(|| {
+        let res = std::fs::create_dir_all(".cache")
+            .and_then(|_| std::fs::write(EXTENSION_PATH, EXTENSION));
+        if let Err(err) = &res {
+            error!("Failed write {EXTENSION_PATH}: {err}");
+        }
+        res.is_ok()
+    });
+    written.then_some(EXTENSION_PATH)
+}
 
 pub struct AsciidoctorBuilder {
     input_file: String,
     destination_dir: String,
     attrs: Vec<String>,
     plugins: Vec<String>,
+    diagram_dir: Option<String>,
+    backend: Option<String>,
+    output_file: Option<String>,
+    source: Option<String>,
 }
 
 impl AsciidoctorBuilder {
@@ -14,6 +40,10 @@ impl AsciidoctorBuilder {
             destination_dir,
             attrs: Default::default(),
             plugins: Default::default(),
+            diagram_dir: None,
+            backend: None,
+            output_file: None,
+            source: None,
         }
     }
 
@@ -23,21 +53,55 @@ impl AsciidoctorBuilder {
     }
 
     pub fn plugin(&mut self, plugin: String) -> &mut Self {
-        if plugin == "asciidoctor-diagram" {
-            self.attrs.push(format!("outdir={}", self.destination_dir));
-        }
         self.plugins.push(plugin);
         self
     }
 
+    /// Where asciidoctor-diagram writes images and its cache, the destination directory by
+    /// default.
+    pub fn diagram_dir(&mut self, dir: String) -> &mut Self {
+        self.diagram_dir = Some(dir);
+        self
+    }
+
+    /// Converter to use instead of HTML, e.g. `pdf` with the asciidoctor-pdf plugin.
+    pub fn backend(&mut self, backend: String) -> &mut Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// File to write the output to instead of returning it.
+    pub fn output(&mut self, file: String) -> &mut Self {
+        self.output_file = Some(file);
+        self
+    }
+
+    /// Content to convert instead of the input file, which still resolves includes and relative
+    /// paths.
+    pub fn source(&mut self, content: String) -> &mut Self {
+        self.source = Some(content);
+        self
+    }
+
     pub async fn build(&self) -> String {
         let mut cmd_ = process::Command::new("asciidoctor");
-        let cmd = cmd_
-            .arg(&self.input_file)
+        let cmd = match &self.source {
+            Some(_) => {
+                let base_dir = Path::new(&self.input_file)
+                    .parent()
+                    .unwrap_or(Path::new("."));
+                cmd_.arg("-").arg("-B").arg(base_dir).stdin(Stdio::piped())
+            }
+            None => cmd_.arg(&self.input_file),
+        };
+        let cmd = cmd
             .arg("-D")
             .arg(&self.destination_dir)
             .arg("-o")
-            .arg("-");
+            .arg(self.output_file.as_deref().unwrap_or("-"));
+        if let Some(backend) = &self.backend {
+            cmd.arg("-b").arg(backend);
+        }
 
         for attr in &self.attrs {
             cmd.arg("-a").arg(attr);
@@ -45,8 +109,29 @@ impl AsciidoctorBuilder {
         for plugin in &self.plugins {
             cmd.arg("-r").arg(plugin);
         }
+        if let Some(path) = extension_path() {
+            cmd.arg("-r").arg(format!("./{path}"));
+        }
+        if self
+            .plugins
+            .iter()
+            .any(|plugin| plugin == "asciidoctor-diagram")
+        {
+            let dir = self.diagram_dir.as_ref().unwrap_or(&self.destination_dir);
+            cmd.arg("-a").arg(format!("imagesoutdir={dir}"));
+            cmd.arg("-a").arg(format!("diagram-cachedir={dir}"));
+        }
 
-        let res = cmd.output().await.unwrap();
+        let Some(source) = &self.source else {
+            let res = cmd.output().await.unwrap();
+            return String::from_utf8_lossy(&res.stdout).to_string();
+        };
+        let mut child = cmd.stdout(Stdio::piped()).spawn().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let source = source.clone();
+        let writer = tokio::spawn(async move { stdin.write_all(source.as_bytes()).await });
+        let res = child.wait_with_output().await.unwrap();
+        let _ = writer.await;
         String::from_utf8_lossy(&res.stdout).to_string()
     }
 }
@@ -0,0 +1,17 @@
+# Loaded into every asciidoctor run by asphinx. Passes the block attributes the
+# HTML converter drops on to asphinx as roles.
+require 'asciidoctor/extensions'
+
+Asciidoctor::Extensions.register do
+  treeprocessor do
+    process do |document|
+      document.find_by(context: :listing, style: 'source').each do |block|
+        # `highlight=2..4,6` becomes `asphinx-highlight-2..4,6`.
+        if (lines = block.attr 'highlight')
+          block.add_role "asphinx-highlight-#{lines.to_s.delete ' '}"
+        end
+      end
+      nil
+    end
+  end
+end
@@ -2,19 +2,107 @@ use std::sync::LazyLock;
 
 use itertools::Itertools;
 use lazy_regex::regex;
-use scraper::{Html, Selector};
+use scraper::{ElementRef, Html, Selector};
+use tracing::*;
 
 pub struct HtmlParser {
     html: Html,
 }
 
+/// Part of the content under a `sect1` or `sect2` heading. Deeper sections are folded into their
+/// `sect2`, and the content before the first section has no anchor.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Section {
+    pub id: Option<String>,
+    pub heading: String,
+    /// Headings of the enclosing sections.
+    pub breadcrumb: Vec<String>,
+    pub text: String,
+}
+
+/// Elements left out of the text extracted from the content.
+#[derive(Debug, Clone, Default)]
+pub struct TextFilter {
+    exclude: Vec<Selector>,
+}
+
+impl TextFilter {
+    /// Selectors that fail to parse are ignored. `exclude_code` also drops code blocks.
+    pub fn new(selectors: &[String], exclude_code: bool) -> Self {
+        let code = exclude_code.then(|| "pre".to_string());
+        let exclude = selectors
+            .iter()
+            .chain(code.iter())
+            .filter_map(|item| match Selector::parse(item) {
+                Ok(selector) => Some(selector),
+                Err(err) => {
+                    warn!("Invalid exclude selector `{item}`: {err}");
+                    None
+                }
+            })
+            .collect();
+        Self { exclude }
+    }
+
+    fn excludes(&self, element: &ElementRef) -> bool {
+        self.exclude.iter().any(|item| item.matches(element))
+    }
+}
+
 static TAG_CONTENT: LazyLock<Selector> = LazyLock::new(|| Selector::parse("#content").unwrap());
-static TAG_BODY: LazyLock<Selector> = LazyLock::new(|| Selector::parse("body").unwrap());
+static TAG_PREAMBLE_PARAGRAPH: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("#preamble .paragraph p").unwrap());
+static TAG_PARAGRAPH: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("#content .paragraph p").unwrap());
+static TAG_RESOURCE: LazyLock<Selector> = LazyLock::new(|| {
+    Selector::parse(
+        "img[src], video[src], video[poster], audio[src], source[src], track[src], \
+         object[data], iframe[src], embed[src], a[href]",
+    )
+    .unwrap()
+});
+/// Attributes of the elements matched by `TAG_RESOURCE` holding the URL of a file.
+static RESOURCE_ATTRS: &[&str] = &["src", "poster", "data", "href"];
 static TAG_IMG: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img").unwrap());
 static TAG_FOOTNOTE: LazyLock<Selector> = LazyLock::new(|| Selector::parse("#footnotes").unwrap());
 static TAG_TITLE: LazyLock<Selector> = LazyLock::new(|| Selector::parse("title").unwrap());
 static TAG_TOC: LazyLock<Selector> = LazyLock::new(|| Selector::parse("#toc").unwrap());
 
+static INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "cite", "code", "del", "em", "i", "ins", "kbd", "mark", "q", "s", "small",
+    "span", "strong", "sub", "sup", "u", "var",
+];
+
+/// Child of an element when extracting its text.
+enum TextNode<'a> {
+    Text(&'a str),
+    Element(ElementRef<'a>),
+}
+
+/// Children of `element` that `filter` keeps, with spaces around block elements to keep the words
+/// of adjacent blocks apart.
+fn text_children<'a>(
+    element: ElementRef<'a>,
+    filter: &'a TextFilter,
+) -> impl Iterator<Item = TextNode<'a>> {
+    element.children().flat_map(move |node| {
+        if let Some(text) = node.value().as_text() {
+            return vec![TextNode::Text(text)];
+        }
+        let Some(child) = ElementRef::wrap(node).filter(|child| !filter.excludes(child)) else {
+            return Vec::new();
+        };
+        match INLINE_ELEMENTS.contains(&child.value().name()) {
+            true => vec![TextNode::Element(child)],
+            false => vec![
+                TextNode::Text(" "),
+                TextNode::Element(child),
+                TextNode::Text(" "),
+            ],
+        }
+    })
+}
+
 impl HtmlParser {
     pub fn new(html: &str) -> Self {
         Self {
@@ -29,12 +117,40 @@ impl HtmlParser {
         None
     }
 
-    pub fn text(&self) -> String {
-        if let Some(v) = self.html.select(&TAG_BODY).next() {
-            return v.text().join("");
-        }
+    /// Text of the content with whitespace normalized.
+    pub fn text(&self, filter: &TextFilter) -> String {
+        let Some(content) = self.html.select(&TAG_CONTENT).next() else {
+            return Default::default();
+        };
+        let mut res = String::new();
+        Self::collect_text(content, filter, &mut res);
+        res.split_whitespace().join(" ")
+    }
 
-        Default::default()
+    /// Text of the first paragraph of the preamble, or of the content when there is no preamble.
+    pub fn first_paragraph(&self, filter: &TextFilter) -> Option<String> {
+        let mut paragraphs = self.html.select(&TAG_PREAMBLE_PARAGRAPH).peekable();
+        let paragraphs: Box<dyn Iterator<Item = ElementRef>> = if paragraphs.peek().is_some() {
+            Box::new(paragraphs)
+        } else {
+            Box::new(self.html.select(&TAG_PARAGRAPH))
+        };
+        paragraphs
+            .map(|item| {
+                let mut res = String::new();
+                Self::collect_text(item, filter, &mut res);
+                res.split_whitespace().join(" ")
+            })
+            .find(|item| !item.is_empty())
+    }
+
+    fn collect_text(element: ElementRef, filter: &TextFilter, res: &mut String) {
+        for node in text_children(element, filter) {
+            match node {
+                TextNode::Text(text) => res.push_str(text),
+                TextNode::Element(child) => Self::collect_text(child, filter, res),
+            }
+        }
     }
 
     pub fn get_title(&self) -> String {
@@ -54,6 +170,75 @@ impl HtmlParser {
         None
     }
 
+    /// The content split into sections, in document order. The content before the first section
+    /// is dropped when it has no text.
+    pub fn sections(&self, filter: &TextFilter) -> Vec<Section> {
+        let Some(content) = self.html.select(&TAG_CONTENT).next() else {
+            return Vec::new();
+        };
+        let mut res = vec![Section::default()];
+        Self::collect_sections(content, filter, 0, &[], &mut res);
+        for item in &mut res {
+            item.text = item.text.split_whitespace().join(" ");
+        }
+        if res[0].text.is_empty() {
+            res.remove(0);
+        }
+        res
+    }
+
+    /// Appends the text under `element` to the section at `current`, starting a new section for
+    /// every `sect1` and `sect2` found.
+    fn collect_sections(
+        element: ElementRef,
+        filter: &TextFilter,
+        current: usize,
+        breadcrumb: &[String],
+        res: &mut Vec<Section>,
+    ) {
+        for node in text_children(element, filter) {
+            let child = match node {
+                TextNode::Text(text) => {
+                    res[current].text.push_str(text);
+                    continue;
+                }
+                TextNode::Element(child) => child,
+            };
+            let heading = child
+                .value()
+                .classes()
+                .any(|class| class == "sect1" || class == "sect2")
+                .then(|| {
+                    child
+                        .child_elements()
+                        .find(|item| matches!(item.value().name(), "h2" | "h3"))
+                })
+                .flatten();
+            let Some(heading) = heading else {
+                Self::collect_sections(child, filter, current, breadcrumb, res);
+                continue;
+            };
+
+            let title = heading.text().join("").split_whitespace().join(" ");
+            res.push(Section {
+                id: heading.value().attr("id").map(String::from),
+                heading: title.clone(),
+                breadcrumb: breadcrumb.to_vec(),
+                text: String::new(),
+            });
+            let idx = res.len() - 1;
+            let mut breadcrumb = breadcrumb.to_vec();
+            breadcrumb.push(title);
+            for item in child
+                .child_elements()
+                .filter(|item| *item != heading && !filter.excludes(item))
+            {
+                res[idx].text.push(' ');
+                Self::collect_sections(item, filter, idx, &breadcrumb, res);
+            }
+        }
+    }
+
     pub fn get_image_urls(&self) -> Vec<String> {
         let mut res = Vec::new();
 
@@ -72,6 +257,36 @@ impl HtmlParser {
         res
     }
 
+    /// Local files referenced by the page: media, embedded objects and frames, and links to
+    /// anything but other pages. Queries and fragments are stripped and duplicates removed.
+    pub fn resource_urls(&self) -> Vec<String> {
+        let re = regex!(r#"^(\w[\w+.-]*:|//|#)"#);
+        let mut res = Vec::new();
+        for item in self.html.select(&TAG_RESOURCE) {
+            for attr in RESOURCE_ATTRS {
+                let Some(val) = item.value().attr(attr) else {
+                    continue;
+                };
+                if val.is_empty() || re.is_match(val) {
+                    continue;
+                }
+                let val = val.split(['?', '#']).next().unwrap_or_default();
+                if item.value().name() == "a"
+                    && (val.ends_with(".html") || val.ends_with(".htm") || val.ends_with('/'))
+                {
+                    continue;
+                }
+                let Ok(url) = urlencoding::decode(val) else {
+                    continue;
+                };
+                if !url.is_empty() && !res.contains(&url.to_string()) {
+                    res.push(url.into());
+                }
+            }
+        }
+        res
+    }
+
     pub fn get_footnotes(&self) -> Option<String> {
         if let Some(item) = self.html.select(&TAG_FOOTNOTE).next() {
             return Some(item.inner_html());
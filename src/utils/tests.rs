@@ -1 +1,2 @@
 mod test_html;
+mod test_tmpl;
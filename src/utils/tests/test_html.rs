@@ -2,7 +2,7 @@
 mod test {
     use minify_html::Cfg;
 
-    use crate::utils::HtmlParser;
+    use crate::utils::{HtmlParser, Section, TextFilter};
 
     fn minify(data: &str) -> String {
         let mut cfg = Cfg::new();
@@ -48,4 +48,101 @@ mod test {
             "<hr><div class=footnote id=_footnotedef_1><a href=#_footnoteref_1>1</a>. <a href=https://zhuanlan.zhihu.com/p/93609693>深入理解 Epoll</a></div>".to_string()
         );
     }
+
+    #[test]
+    fn test_text() {
+        let html = HtmlParser::new(include_str!("index.html"));
+        let text = html.text(&TextFilter::new(&[], false));
+        assert!(
+            text.starts_with("static void SetSocketNonBlocking(int fd) { int flags = fcntl("),
+            "{text}"
+        );
+        assert!(
+            text.ends_with("(char*)&tcpNoDelay, sizeof(int)); }"),
+            "{text}"
+        );
+        // The code block is all the text of the page.
+        assert_eq!(html.text(&TextFilter::new(&[], true)), "");
+
+        let html = HtmlParser::new(
+            r#"<body><div id="toc">Contents</div><div id="content"><p>Call
+            <code>epoll_wait</code><sup class="footnote">[1]</sup> here.</p>
+            <pre><code>epoll_wait(fd); <b class="conum">(1)</b></code></pre></div>
+            <div id="footer">Last updated</div></body>"#,
+        );
+        assert_eq!(
+            html.text(&TextFilter::new(
+                &["sup.footnote".into(), ".conum".into(), "[".into()],
+                false
+            )),
+            "Call epoll_wait here. epoll_wait(fd);"
+        );
+        assert_eq!(
+            html.text(&TextFilter::new(&["sup.footnote".into()], true)),
+            "Call epoll_wait here."
+        );
+    }
+
+    #[test]
+    fn test_sections() {
+        let html = HtmlParser::new(
+            r#"<body><div id="toc"><h2 id="toctitle">Contents</h2></div><div id="content">
+            <div id="preamble"><div class="sectionbody"><p>Intro</p></div></div>
+            <div class="sect1"><h2 id="_epoll">Epoll
+              模型</h2><div class="sectionbody"><p>Events</p>
+              <div class="sect2"><h3 id="_et">ET</h3><p>Edge</p>
+                <div class="sect3"><h4 id="_deep">Deep</h4><p>triggered</p></div></div>
+              <p>More<b>over</b></p></div></div></div></body>"#,
+        );
+        assert_eq!(
+            html.sections(&TextFilter::default()),
+            vec![
+                Section {
+                    text: "Intro".into(),
+                    ..Default::default()
+                },
+                Section {
+                    id: Some("_epoll".into()),
+                    heading: "Epoll 模型".into(),
+                    breadcrumb: vec![],
+                    text: "Events Moreover".into(),
+                },
+                Section {
+                    id: Some("_et".into()),
+                    heading: "ET".into(),
+                    breadcrumb: vec!["Epoll 模型".into()],
+                    text: "Edge Deep triggered".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resource_urls() {
+        let html = HtmlParser::new(
+            r##"<body><div id="content">
+            <img src="assets/UDP_CS%E6%A8%A1%E5%9E%8B.png"><img src="https://example.com/a.png">
+            <video src="media/demo.mp4" poster="media/demo.jpg"><track src="media/demo.vtt"></video>
+            <audio><source src="media/demo.ogg?v=1"></audio>
+            <object data="diag-1.svg" type="image/svg+xml"></object>
+            <iframe src="embed/map.html"></iframe>
+            <a href="files/spec.pdf#page=2">Spec</a><a href="other.html">Other</a>
+            <a href="#_anchor">Anchor</a><a href="mailto:a@b.c">Mail</a>
+            <a href="files/spec.pdf">Spec again</a><a href="data:text/plain,x">Data</a>
+            </div></body>"##,
+        );
+        assert_eq!(
+            html.resource_urls(),
+            vec![
+                "assets/UDP_CS模型.png",
+                "media/demo.mp4",
+                "media/demo.jpg",
+                "media/demo.vtt",
+                "media/demo.ogg",
+                "diag-1.svg",
+                "embed/map.html",
+                "files/spec.pdf",
+            ]
+        );
+    }
 }
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use crate::{assets::AssetManifest, config, theme::Theme, url::UrlBuilder, utils::Tmpl};
+
+    #[test]
+    fn test_describe_error() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let mut tmpl = Tmpl::new(&theme, UrlBuilder::default(), Default::default());
+        tmpl.engine
+            .add_template("broken", "<h1>{{ title }}</h1>\n{{ range(title) }}\n")
+            .unwrap();
+
+        let err = tmpl
+            .engine
+            .get_template("broken")
+            .unwrap()
+            .render(minijinja::context! { title => "x" })
+            .unwrap_err();
+        let res = tmpl.describe_error(&err);
+
+        assert!(res.contains("--> broken.html:2"), "{res}");
+        assert!(res.contains("{{ range(title) }}"), "{res}");
+        assert!(res.contains('^'), "{res}");
+    }
+
+    #[test]
+    fn test_url_for() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let mut tmpl = Tmpl::new(&theme, UrlBuilder::new("/docs", true), Default::default());
+        tmpl.engine
+            .add_template("link", r#"{{ url_for("assets/style.css") }}"#)
+            .unwrap();
+
+        let res = tmpl
+            .engine
+            .get_template("link")
+            .unwrap()
+            .render(minijinja::context! { path => "a/b/c.html" })
+            .unwrap();
+        assert_eq!(res, "../../assets/style.css");
+    }
+
+    #[test]
+    fn test_asset_url() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let mut assets = BTreeMap::new();
+        assets.insert("style.css".to_string(), b"a {}".to_vec());
+        let (manifest, _) = AssetManifest::build(assets, true);
+        let name = manifest.get("style.css").unwrap().to_string();
+        let mut tmpl = Tmpl::new(&theme, UrlBuilder::new("/docs", false), Arc::new(manifest));
+        tmpl.engine
+            .add_template(
+                "link",
+                r#"{{ asset_url("style.css") }} {{ asset_url("app.js") }}"#,
+            )
+            .unwrap();
+
+        let res = tmpl
+            .engine
+            .get_template("link")
+            .unwrap()
+            .render(minijinja::context! { path => "a/b.html" })
+            .unwrap();
+        assert_eq!(res, format!("/docs/assets/{name} /docs/assets/app.js"));
+    }
+
+    #[test]
+    fn test_render_nav() {
+        let theme = Theme::load(None, &config::Theme::default()).unwrap();
+        let mut tmpl = Tmpl::new(&theme, UrlBuilder::default(), Default::default());
+        tmpl.engine
+            .add_template(
+                "sidebar",
+                r#"{% from "nav" import render_nav %}{{ render_nav(nav, root, path, expanded) }}"#,
+            )
+            .unwrap();
+
+        let nav = minijinja::Value::from_serialize(serde_json::json!([{
+            "name": "a",
+            "path": "a",
+            "url": "a/index.html",
+            "is_directory": true,
+            "children": [
+                { "name": "1", "title": "One", "path": "a/1", "url": "a/1.html", "children": [] }
+            ]
+        }]));
+        let res = tmpl
+            .engine
+            .get_template("sidebar")
+            .unwrap()
+            .render(minijinja::context! { nav, root => "../", path => "a/1.html", expanded => vec!["a"] })
+            .unwrap();
+
+        assert!(res.contains("<details open>"), "{res}");
+        assert!(
+            res.contains(r#"<a href="../a/1.html" aria-current="page">One</a>"#),
+            "{res}"
+        );
+    }
+}
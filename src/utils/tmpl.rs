@@ -1,29 +1,55 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
 use super::jinjaext::{self, LocalTime};
-use minijinja::Environment;
+use crate::{assets::AssetManifest, theme::Theme, url::UrlBuilder};
+use minijinja::{Environment, State};
 use tracing::error;
 
 #[derive(Debug)]
 pub struct Tmpl {
     pub engine: Box<Environment<'static>>,
+    /// Files the templates were loaded from, keyed by template name.
+    sources: Arc<RwLock<HashMap<String, PathBuf>>>,
 }
 
 impl Tmpl {
-    pub fn new(theme_dir: String) -> Self {
+    pub fn new(theme: &Theme, urls: UrlBuilder, assets: Arc<AssetManifest>) -> Self {
         let mut engine = Box::new(Environment::new());
+        engine.set_debug(true);
+
+        let sources: Arc<RwLock<HashMap<String, PathBuf>>> = Default::default();
+        let layout_dirs = theme.layout_dirs();
+        let embedded = theme.clone();
+        let loaded = sources.clone();
         engine.set_loader(move |name| {
-            let file_name = format!("{theme_dir}/layouts/{name}.html");
-            match std::fs::read_to_string(&file_name) {
-                Ok(v) => Ok(Some(v)),
-                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
-                Err(err) => {
-                    error!("failed get {file_name}");
-                    Err(minijinja::Error::new(
-                        minijinja::ErrorKind::InvalidOperation,
-                        "could not read template",
-                    )
-                    .with_source(err))
+            for dir in &layout_dirs {
+                let file_name = dir.join(format!("{name}.html"));
+                match std::fs::read_to_string(&file_name) {
+                    Ok(v) => {
+                        loaded.write().unwrap().insert(name.into(), file_name);
+                        return Ok(Some(v));
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => {
+                        error!("failed get {}", file_name.display());
+                        return Err(minijinja::Error::new(
+                            minijinja::ErrorKind::InvalidOperation,
+                            "could not read template",
+                        )
+                        .with_source(err));
+                    }
                 }
             }
+            let layout = embedded.embedded_layout(name).map(String::from);
+            if layout.is_some() {
+                let file_name = PathBuf::from(format!("<embedded>/layouts/{name}.html"));
+                loaded.write().unwrap().insert(name.into(), file_name);
+            }
+            Ok(layout)
         });
 
         engine.add_filter("minify", jinjaext::minify_jinja);
@@ -34,7 +60,67 @@ impl Tmpl {
             "now",
             minijinja::value::Value::from_object(LocalTime::default()),
         );
+        engine.add_global(
+            "theme",
+            minijinja::value::Value::from_serialize(&theme.params),
+        );
+        // `url_for("assets/style.css")` links a file of the output directory from the page being
+        // rendered.
+        let asset_urls = urls.clone();
+        engine.add_function("url_for", move |state: &State, target: String| -> String {
+            urls.page(&current_path(state), &target)
+        });
+        // `asset_url("style.css")` links a theme asset under the name it is published as.
+        engine.add_function("asset_url", move |state: &State, name: String| -> String {
+            asset_urls.page(&current_path(state), &assets.asset_path(&name))
+        });
+
+        Self { engine, sources }
+    }
 
-        Self { engine }
+    /// Describes a template error with the file and line it happens at, followed by a snippet of
+    /// the template source pointing to the faulty expression.
+    pub fn describe_error(&self, err: &minijinja::Error) -> String {
+        let mut res = String::new();
+        let mut cur: Option<&(dyn std::error::Error + 'static)> = Some(err);
+        while let Some(err) = cur {
+            if let Some(err) = err.downcast_ref::<minijinja::Error>() {
+                if !res.is_empty() {
+                    res.push_str("\ncaused by: ");
+                }
+                res.push_str(&err.kind().to_string());
+                if let Some(detail) = err.detail() {
+                    res.push_str(&format!(": {detail}"));
+                }
+                if let Some(name) = err.name() {
+                    let file = self
+                        .sources
+                        .read()
+                        .unwrap()
+                        .get(name)
+                        .map(|path| path.display().to_string())
+                        .unwrap_or(format!("{name}.html"));
+                    match err.line() {
+                        Some(line) => res.push_str(&format!("\n  --> {file}:{line}")),
+                        None => res.push_str(&format!("\n  --> {file}")),
+                    }
+                }
+                if err.template_source().is_some() {
+                    res.push_str(&format!("\n{}", err.display_debug_info()));
+                }
+            } else {
+                res.push_str(&format!("\ncaused by: {err}"));
+            }
+            cur = err.source();
+        }
+        res
     }
 }
+
+/// Output path of the page being rendered.
+fn current_path(state: &State) -> String {
+    state
+        .lookup("path")
+        .map(|path| path.to_string())
+        .unwrap_or_default()
+}
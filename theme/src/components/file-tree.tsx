@@ -9,6 +9,7 @@ import {
   X,
 } from 'lucide-react';
 import { useCallback, useEffect, useState } from 'react';
+import { currentPath, siteUrl } from '../lib/site';
 import { Button } from './ui/button';
 import { Input } from './ui/input';
 import { ScrollArea } from './ui/scroll-area';
@@ -21,6 +22,12 @@ interface FileTreeNode {
   is_directory: boolean;
   children: FileTreeNode[];
   level: number;
+  last_modified?: string;
+  tags?: string[];
+  summary?: string;
+  word_count?: number;
+  reading_time?: number;
+  shard?: string;
 }
 
 interface FileTreeData {
@@ -28,6 +35,48 @@ interface FileTreeData {
   flat_list: FileTreeNode[];
 }
 
+interface CompactNode extends Omit<FileTreeNode, 'children' | 'level'> {
+  parent?: number;
+}
+
+function flatten(nodes: FileTreeNode[]): FileTreeNode[] {
+  return nodes.flatMap(node => [...flatten(node.children), node]);
+}
+
+// Accepts both the nested and the compact `filetree.json` formats.
+async function fetchTree(path: string, level: number): Promise<FileTreeNode[]> {
+  const response = await fetch(siteUrl(path));
+  if (!response.ok) {
+    throw new Error('Failed to load file tree data');
+  }
+  const json = await response.json();
+  if (!('nodes' in json)) {
+    return (json as FileTreeData).root;
+  }
+
+  const roots: FileTreeNode[] = [];
+  const nodes: FileTreeNode[] = [];
+  for (const item of json.nodes as CompactNode[]) {
+    const parent = item.parent === undefined ? undefined : nodes[item.parent];
+    const node: FileTreeNode = {
+      ...item,
+      is_directory: item.is_directory ?? false,
+      children: [],
+      level: parent ? parent.level + 1 : level,
+    };
+    nodes.push(node);
+    (parent ? parent.children : roots).push(node);
+  }
+  return roots;
+}
+
+// Loads the children of a sharded section into the tree.
+async function loadShard(node: FileTreeNode): Promise<void> {
+  if (node.shard && node.children.length === 0) {
+    node.children = await fetchTree(node.shard, node.level + 1);
+  }
+}
+
 interface FileTreeProps {
   currentPath?: string;
 }
@@ -67,10 +116,10 @@ function TreeNode({
   }, [node.path, node.is_directory, onToggle]);
 
   const handleClick = useCallback(() => {
-    if (node.is_directory) {
+    if (node.is_directory && !node.url) {
       handleToggle();
     } else if (node.url) {
-      window.location.href = `/${node.url}`;
+      window.location.href = siteUrl(node.url);
     }
   }, [node.is_directory, node.url, handleToggle]);
 
@@ -86,6 +135,7 @@ function TreeNode({
           isActive ? 'bg-accent font-medium text-accent-foreground' : ''
         }`}
         style={{ paddingLeft: `${node.level * 16 + 8}px` }}
+        title={node.summary}
         onClick={handleClick}
       >
         {node.is_directory ? (
@@ -188,10 +238,7 @@ export default function FileTree({
 
   const actualCurrentPath =
     propCurrentPath ||
-    (typeof window !== 'undefined'
-      ? window.location.pathname.replace(/^\//, '').replace(/\/$/, '') ||
-        'index.html'
-      : 'index.html');
+    (typeof window !== 'undefined' ? currentPath() : 'index.html');
 
   // Check if mobile
   useEffect(() => {
@@ -208,12 +255,14 @@ export default function FileTree({
   useEffect(() => {
     const loadData = async () => {
       try {
-        const response = await fetch('/filetree.json');
-        if (!response.ok) {
-          throw new Error('Failed to load file tree data');
-        }
-        const treeData: FileTreeData = await response.json();
-        setData(treeData);
+        const root = await fetchTree('filetree.json', 0);
+        // Only the section of the current page is loaded up front.
+        await Promise.all(
+          root
+            .filter(node => actualCurrentPath.startsWith(`${node.path}/`))
+            .map(loadShard),
+        );
+        setData({ root, flat_list: flatten(root) });
 
         // Auto-expand path to current file
         if (actualCurrentPath) {
@@ -273,17 +322,32 @@ export default function FileTree({
     localStorage.setItem('filetree-collapsed', isCollapsed.toString());
   }, [isCollapsed]);
 
-  const handleToggle = useCallback((path: string) => {
-    setExpandedNodes(prev => {
-      const newSet = new Set(prev);
-      if (newSet.has(path)) {
-        newSet.delete(path);
-      } else {
-        newSet.add(path);
+  const handleToggle = useCallback(
+    (path: string) => {
+      const section = data?.root.find(node => node.path === path);
+      if (section?.shard && section.children.length === 0) {
+        loadShard(section)
+          .then(() =>
+            setData(prev =>
+              prev ? { root: prev.root, flat_list: flatten(prev.root) } : prev,
+            ),
+          )
+          .catch(err =>
+            setError(err instanceof Error ? err.message : 'Unknown error'),
+          );
       }
-      return newSet;
-    });
-  }, []);
+      setExpandedNodes(prev => {
+        const newSet = new Set(prev);
+        if (newSet.has(path)) {
+          newSet.delete(path);
+        } else {
+          newSet.add(path);
+        }
+        return newSet;
+      });
+    },
+    [data],
+  );
 
   const handleSearch = useCallback(
     (e: React.ChangeEvent<HTMLInputElement>) => {
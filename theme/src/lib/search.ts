@@ -0,0 +1,40 @@
+import type { SearchHit, SearchRequest, SearchResponse } from '../worker';
+import { siteRoot } from './site';
+
+/** Runs queries against the built-in search index in a web worker. */
+export class SearchClient {
+  private worker = new Worker(new URL('../worker.ts', import.meta.url), {
+    type: 'module',
+  });
+  private nextId = 0;
+  private pending = new Map<
+    number,
+    { resolve: (hits: SearchHit[]) => void; reject: (err: Error) => void }
+  >();
+
+  constructor() {
+    this.worker.onmessage = ({ data }: MessageEvent<SearchResponse>) => {
+      const pending = this.pending.get(data.id);
+      this.pending.delete(data.id);
+      if (data.error !== undefined) {
+        pending?.reject(new Error(data.error));
+      } else {
+        pending?.resolve(data.hits ?? []);
+      }
+    };
+  }
+
+  search(query: string, limit?: number): Promise<SearchHit[]> {
+    const id = this.nextId++;
+    const request: SearchRequest = {
+      id,
+      root: siteRoot().href,
+      query,
+      limit,
+    };
+    return new Promise((resolve, reject) => {
+      this.pending.set(id, { resolve, reject });
+      this.worker.postMessage(request);
+    });
+  }
+}
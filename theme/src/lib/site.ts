@@ -0,0 +1,22 @@
+/** URL of the site root, published by the page template in `<meta name="asphinx-root">`. */
+export function siteRoot(): URL {
+  const meta = document.querySelector<HTMLMetaElement>(
+    'meta[name="asphinx-root"]',
+  );
+  return new URL(meta?.content ?? '/', document.baseURI);
+}
+
+/** Resolves a path relative to the site root, as found in `filetree.json`. */
+export function siteUrl(path: string): string {
+  return new URL(path.replace(/^\//, ''), siteRoot()).href;
+}
+
+/** Path of the current page relative to the site root. */
+export function currentPath(): string {
+  const root = siteRoot().pathname;
+  const path = decodeURI(window.location.pathname);
+  const res = path.startsWith(root)
+    ? path.slice(root.length)
+    : path.replace(/^\//, '');
+  return res.replace(/\/$/, '') || 'index.html';
+}
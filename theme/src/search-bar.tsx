@@ -6,6 +6,9 @@ import { Input } from './components/ui/input';
 import { Label } from './components/ui/label';
 import { ScrollArea } from './components/ui/scroll-area';
 import { Skeleton } from './components/ui/skeleton';
+import { SearchClient } from './lib/search';
+import { siteRoot, siteUrl } from './lib/site';
+import type { SearchHit } from './worker';
 
 interface SearchResultItem {
   path: string;
@@ -20,6 +23,8 @@ export default function SearchBar() {
   const [results, setResults] = useState<SearchResultItem[]>([]);
   const [isLoading, setIsLoading] = useState(false);
   const [pagefind, setPagefind] = useState<PagefindInstance | null>(null);
+  // The built-in index is used when pagefind is not available.
+  const [fallback, setFallback] = useState<SearchClient | null>(null);
   const [error, setError] = useState<string | null>(null);
 
   useEffect(() => {
@@ -27,17 +32,21 @@ export default function SearchBar() {
       try {
         // eslint-disable-next-line @typescript-eslint/no-implied-eval
         const importPagefind = new Function(
-          'return import("/pagefind/pagefind.js")',
+          'url',
+          'return import(url)',
+        );
+        const pagefindModule = await importPagefind(
+          siteUrl('pagefind/pagefind.js'),
         );
-        const pagefindModule = await importPagefind();
 
         const pf = pagefindModule as PagefindInstance;
 
+        await pf.options({ baseUrl: siteRoot().pathname });
         await pf.init();
         setPagefind(pf);
       } catch (err) {
-        console.error('Failed to initialize Pagefind:', err);
-        setError('search initialization failed');
+        console.warn('Failed to initialize Pagefind, using search/:', err);
+        setFallback(new SearchClient());
       }
     };
 
@@ -54,19 +63,46 @@ export default function SearchBar() {
         return null;
       };
 
+      // Link to the section with the most matches.
+      const section = [...fragment.sub_results].sort(
+        (a, b) => b.locations.length - a.locations.length,
+      )[0];
+      const url = section?.url ?? fragment.url;
+      const title =
+        fragment.meta.title ||
+        fragment.url.split('/').pop()?.replace('.html', '') ||
+        'Untitled';
+
       return {
-        path: fragment.url.replace(/^\//, ''),
-        content: fragment.excerpt.replace(/<\/?mark>/g, ''),
+        path: url.startsWith(siteRoot().pathname)
+          ? url.slice(siteRoot().pathname.length)
+          : url.replace(/^\//, ''),
+        content: (section?.excerpt ?? fragment.excerpt).replace(
+          /<\/?mark>/g,
+          '',
+        ),
         title:
-          fragment.meta.title ||
-          fragment.url.split('/').pop()?.replace('.html', '') ||
-          'Untitled',
+          section && section.url !== fragment.url
+            ? `${title} › ${section.title}`
+            : title,
         time: extractDate(fragment.meta),
       };
     },
     [],
   );
 
+  const convertHit = useCallback(
+    (hit: SearchHit): SearchResultItem => ({
+      path: hit.url,
+      content: hit.excerpt,
+      title: [hit.title, ...(hit.breadcrumb ?? []), hit.heading]
+        .filter(Boolean)
+        .join(' › '),
+      time: hit.date ? new Date(hit.date) : null,
+    }),
+    [],
+  );
+
   const getDefaultResults = useCallback(async (): Promise<
     SearchResultItem[]
   > => {
@@ -103,6 +139,21 @@ export default function SearchBar() {
 
   const performSearch = useCallback(
     async (query: string) => {
+      if (!pagefind && fallback) {
+        setIsLoading(true);
+        setError(null);
+        try {
+          const hits = await fallback.search(query);
+          setResults(hits.map(convertHit));
+        } catch (err) {
+          console.error('Search failed:', err);
+          setError('search failed, please try again later');
+          setResults([]);
+        } finally {
+          setIsLoading(false);
+        }
+        return;
+      }
       if (!pagefind) {
         return;
       }
@@ -153,7 +204,7 @@ export default function SearchBar() {
         setIsLoading(false);
       }
     },
-    [pagefind, convertPagefindResult, getDefaultResults],
+    [pagefind, fallback, convertHit, convertPagefindResult, getDefaultResults],
   );
 
   useEffect(() => {
@@ -206,7 +257,7 @@ export default function SearchBar() {
                 <div key={`${item.path}-${index}`} className="w-full">
                   <a
                     className="flex w-full flex-col items-start rounded border p-2 shadow"
-                    href={`/${item.path}`}
+                    href={siteUrl(item.path)}
                   >
                     <Label className="w-full">
                       <div className="flex min-w-0 justify-between overflow-x-hidden text-ellipsis whitespace-nowrap text-lg">
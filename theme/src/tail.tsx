@@ -63,3 +63,15 @@ if (fileTreeMobile) {
   const mobileTree = reactDom.createRoot(fileTreeMobile);
   mobileTree.render(<FileTree />);
 }
+
+// Copy buttons for the code blocks asphinx gives the text to copy
+for (const pre of document.querySelectorAll<HTMLElement>('pre[data-copy]')) {
+  const button = document.createElement('button');
+  button.className = 'copy-code';
+  button.textContent = 'Copy';
+  button.onclick = () =>
+    navigator.clipboard.writeText(pre.dataset.copy ?? '').then(() => {
+      button.textContent = 'Copied';
+    });
+  pre.closest('.listingblock')?.append(button);
+}
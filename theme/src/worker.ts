@@ -0,0 +1,191 @@
+// Queries the search index written by `src/search.rs`. Only `search/index.json` and the term
+// shards a query needs are downloaded, shards are cached for the lifetime of the worker.
+
+// A section of a page, `url` includes the anchor of the section.
+export interface SearchDoc {
+  url: string;
+  title: string;
+  heading?: string;
+  breadcrumb?: string[];
+  date?: string;
+  excerpt: string;
+  // Only set on the first section of a page.
+  summary?: string;
+  word_count?: number;
+  reading_time?: number;
+}
+
+export interface SearchHit extends SearchDoc {
+  score: number;
+}
+
+export interface SearchRequest {
+  id: number;
+  root: string;
+  query: string;
+  limit?: number;
+}
+
+export interface SearchResponse {
+  id: number;
+  hits?: SearchHit[];
+  error?: string;
+}
+
+interface SearchMeta {
+  version: number;
+  shards: number;
+  docs: SearchDoc[];
+}
+
+// [doc, score]
+type Posting = [number, number];
+
+const MAX_WORD_LENGTH = 64;
+
+function isCjk(code: number): boolean {
+  return (
+    (code >= 0x3040 && code <= 0x30ff) ||
+    (code >= 0x3400 && code <= 0x4dbf) ||
+    (code >= 0x4e00 && code <= 0x9fff) ||
+    (code >= 0xac00 && code <= 0xd7af) ||
+    (code >= 0xf900 && code <= 0xfaff) ||
+    (code >= 0x20000 && code <= 0x2a6df)
+  );
+}
+
+// Must stay in sync with `tokenize` in src/search.rs.
+export function tokenize(text: string): string[] {
+  const res: string[] = [];
+  let word = '';
+  let run: string[] = [];
+  const flushCjk = () => {
+    if (run.length === 1) {
+      res.push(run[0]);
+    } else {
+      for (let i = 0; i + 1 < run.length; i++) {
+        res.push(run[i] + run[i + 1]);
+      }
+    }
+    run = [];
+  };
+
+  for (const c of text) {
+    if (isCjk(c.codePointAt(0) ?? 0)) {
+      if (word) {
+        res.push(word);
+        word = '';
+      }
+      run.push(c);
+      continue;
+    }
+    if (run.length > 0) {
+      flushCjk();
+    }
+    if (/[\p{L}\p{N}]/u.test(c)) {
+      if ([...word].length < MAX_WORD_LENGTH) {
+        word += c.toLowerCase();
+      }
+    } else if (word) {
+      res.push(word);
+      word = '';
+    }
+  }
+  if (word) {
+    res.push(word);
+  }
+  if (run.length > 0) {
+    flushCjk();
+  }
+  return res;
+}
+
+// FNV-1a, must stay in sync with `shard_of` in src/search.rs.
+export function shardOf(term: string, shards: number): number {
+  let hash = 0x811c9dc5;
+  for (const byte of new TextEncoder().encode(term)) {
+    hash = Math.imul(hash ^ byte, 0x01000193) >>> 0;
+  }
+  return hash % shards;
+}
+
+class SearchIndex {
+  private meta: Promise<SearchMeta>;
+  private shards = new Map<number, Promise<Record<string, Posting[]>>>();
+
+  constructor(private root: string) {
+    this.meta = this.fetchJson('search/index.json');
+  }
+
+  private async fetchJson<T>(path: string): Promise<T> {
+    const response = await fetch(new URL(path, this.root));
+    if (!response.ok) {
+      throw new Error(`Failed to load ${path}`);
+    }
+    return response.json();
+  }
+
+  private async postings(term: string): Promise<Posting[]> {
+    const { shards } = await this.meta;
+    const shard = shardOf(term, shards);
+    let terms = this.shards.get(shard);
+    if (!terms) {
+      terms = this.fetchJson(`search/terms/${shard}.json`);
+      this.shards.set(shard, terms);
+    }
+    return (await terms)[term] ?? [];
+  }
+
+  // Documents containing every term of the query, best matches first.
+  async search(query: string, limit = 10): Promise<SearchHit[]> {
+    const terms = [...new Set(tokenize(query))];
+    if (terms.length === 0) {
+      return [];
+    }
+    const { docs } = await this.meta;
+    const matches = new Map<number, { score: number; terms: number }>();
+    const postingLists = await Promise.all(terms.map(t => this.postings(t)));
+    for (const postings of postingLists) {
+      for (const [doc, score] of postings) {
+        const match = matches.get(doc) ?? { score: 0, terms: 0 };
+        match.score += score;
+        match.terms += 1;
+        matches.set(doc, match);
+      }
+    }
+
+    return [...matches.entries()]
+      .filter(([, match]) => match.terms === terms.length)
+      .sort(([, a], [, b]) => b.score - a.score)
+      .slice(0, limit)
+      .map(([doc, match]) => ({ ...docs[doc], score: match.score }));
+  }
+}
+
+const indexes = new Map<string, SearchIndex>();
+
+interface WorkerScope {
+  onmessage: ((event: MessageEvent<SearchRequest>) => void) | null;
+  postMessage: (message: SearchResponse) => void;
+}
+
+if (typeof document === 'undefined') {
+  const scope = self as unknown as WorkerScope;
+  scope.onmessage = async ({ data }) => {
+    let index = indexes.get(data.root);
+    if (!index) {
+      index = new SearchIndex(data.root);
+      indexes.set(data.root, index);
+    }
+    try {
+      const hits = await index.search(data.query, data.limit);
+      scope.postMessage({ id: data.id, hits });
+    } catch (err) {
+      indexes.delete(data.root);
+      scope.postMessage({
+        id: data.id,
+        error: err instanceof Error ? err.message : 'Unknown error',
+      });
+    }
+  };
+}